@@ -0,0 +1,115 @@
+//! Benchmarks of building and walking the sample tree of large synthetic captures.
+//!
+//! The sampler modules are included directly, since spinsample is a binary crate.
+//! Building is compared against the previous tree, which searched the children of every node
+//! linearly.
+
+// Most of the sample tree API isn't used by the benchmarks,
+// and the tests of the included modules aren't run as part of them.
+#![allow(dead_code)]
+#![cfg_attr(test, allow(unused_imports))]
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+#[path = "../src/sampler/sample_point.rs"]
+mod sample_point;
+#[path = "../src/sampler/thread_sample.rs"]
+mod thread_sample;
+
+use std::time::Duration;
+
+pub use sample_point::SamplePoint;
+use thread_sample::ThreadSample;
+
+pub type Tid = u32;
+
+/// Backtraces of a dispatcher loop calling hundreds of different handlers,
+/// each of which spends its time in a few different functions.
+fn wide_backtraces() -> Vec<Vec<u64>> {
+    (0..100_000u64)
+        .map(|index| vec![1, 2, 3, 1_000 + index % 500, 100_000 + index % 7])
+        .collect()
+}
+
+/// Backtraces of a deeply recursive algorithm, which only differ in their innermost frames.
+fn deep_backtraces() -> Vec<Vec<u64>> {
+    (0..20_000u64)
+        .map(|index| {
+            let mut backtrace: Vec<u64> = (1..=200).collect();
+            backtrace.extend([1_000 + index % 50, 2_000 + index % 3]);
+            backtrace
+        })
+        .collect()
+}
+
+fn build_sample_tree(backtraces: &[Vec<u64>]) -> ThreadSample {
+    let mut thread_sample = ThreadSample::new(1, Duration::default(), Duration::default());
+    for backtrace in backtraces {
+        thread_sample.add_backtrace(backtrace.iter());
+    }
+    thread_sample
+}
+
+/// Node of the previous sample tree, whose children are owned by their parent.
+struct LinearNode {
+    address: u64,
+    count: u32,
+    children: Vec<LinearNode>,
+}
+
+fn add_linear_backtrace<'a>(node: &mut LinearNode, mut backtrace: impl Iterator<Item = &'a u64>) {
+    let Some(&address) = backtrace.next() else {
+        return;
+    };
+
+    if let Some(child) = node
+        .children
+        .iter_mut()
+        .find(|child| child.address == address)
+    {
+        child.count += 1;
+        add_linear_backtrace(child, backtrace);
+    } else {
+        let mut child = LinearNode {
+            address,
+            count: 1,
+            children: Vec::new(),
+        };
+        add_linear_backtrace(&mut child, backtrace);
+        node.children.push(child);
+    }
+}
+
+fn build_linear_sample_tree(backtraces: &[Vec<u64>]) -> LinearNode {
+    let mut root = LinearNode {
+        address: 0,
+        count: 0,
+        children: Vec::new(),
+    };
+    for backtrace in backtraces {
+        root.count += 1;
+        add_linear_backtrace(&mut root, backtrace.iter());
+    }
+    root
+}
+
+fn sample_tree_benchmark(c: &mut Criterion) {
+    for (name, backtraces) in [("wide", wide_backtraces()), ("deep", deep_backtraces())] {
+        let mut group = c.benchmark_group(format!("build {} sample tree", name));
+        group.bench_function("arena", |b| {
+            b.iter(|| build_sample_tree(black_box(&backtraces)))
+        });
+        group.bench_function("linear scan", |b| {
+            b.iter(|| build_linear_sample_tree(black_box(&backtraces)))
+        });
+        group.finish();
+
+        let thread_sample = build_sample_tree(&backtraces);
+        c.bench_function(&format!("walk {} sample tree", name), |b| {
+            b.iter(|| black_box(&thread_sample).sample_tree_dfs_iter().count())
+        });
+    }
+}
+
+criterion_group!(benches, sample_tree_benchmark);
+criterion_main!(benches);
@@ -3,11 +3,14 @@ use std::path::PathBuf;
 use std::process::ExitCode;
 use std::time::Duration;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use windows::Win32::System::SystemInformation::GetLocalTime;
 
 mod cancel_status;
 mod process_iterator;
+mod process_selector;
+mod report;
+mod resource_owner;
 mod sampler;
 mod thread_iterator;
 
@@ -16,10 +19,18 @@ pub use thread_iterator::*;
 use windows::Win32::UI::Shell::ShellExecuteW;
 
 #[derive(Parser, Debug)]
-#[command(override_usage = "spinsample <PROCESS> [DURATION] [INTERVAL] [Options]")]
+#[command(
+    override_usage = "spinsample <PROCESS> [DURATION] [INTERVAL] [Options]\n       spinsample --port <PORT> | --file <PATH> [Options]\n       spinsample <COMMAND> [Options]",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Options {
-    /// The process pid or name to sample
-    process: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// The process pid or name to sample, or several separated by commas to sample them together,
+    /// except with the glob and regex match modes
+    #[arg(required_unless_present_any = ["port", "file"])]
+    process: Option<String>,
     /// Duration in seconds, default is 10
     duration: Option<u64>,
     /// Sampling interval in milliseconds, default is 1
@@ -27,44 +38,363 @@ struct Options {
     /// Wait until the specified process exists, then start sampling
     #[arg(short = 'w', long = "wait")]
     wait: bool,
+    /// Also sample all descendants of the processes, including the ones created during the capture
+    #[arg(long = "tree")]
+    tree: bool,
+    /// Sample the processes listening on the specified TCP or UDP port instead of a named process
+    #[arg(long = "port", value_name = "PORT", conflicts_with = "process")]
+    port: Option<u16>,
+    /// Sample the processes that have the specified file open instead of a named process
+    #[arg(long = "file", value_name = "PATH", conflicts_with_all = ["process", "port"])]
+    file: Option<PathBuf>,
+    /// Duration in seconds, for when no process is given, such as with --port or --file
+    #[arg(long = "duration", value_name = "SECONDS", conflicts_with = "duration")]
+    duration_seconds: Option<u64>,
+    /// Sampling interval in milliseconds, for when no process is given, such as with --port or --file
+    #[arg(
+        long = "interval",
+        value_name = "MILLISECONDS",
+        conflicts_with = "interval"
+    )]
+    interval_milliseconds: Option<u64>,
+    #[command(flatten)]
+    selection: SelectionOptions,
+    #[command(flatten)]
+    output: OutputOptions,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Load a previously saved capture and generate the sample analysis output again
+    Report {
+        /// The capture file, as written by --format json
+        capture: PathBuf,
+        #[command(flatten)]
+        output: OutputOptions,
+    },
+    /// Merge several saved captures into one aggregate process-wide sample tree
+    Merge {
+        /// The capture files, as written by --format json
+        #[arg(required = true)]
+        captures: Vec<PathBuf>,
+        #[command(flatten)]
+        output: OutputOptions,
+    },
+    /// Launch a command line and sample it from startup until it exits
+    Run {
+        /// Sample for at most the specified number of seconds, default is until the process exits
+        #[arg(long = "duration", value_name = "SECONDS")]
+        duration: Option<u64>,
+        /// Sampling interval in milliseconds, default is 1
+        #[arg(long = "interval", value_name = "MILLISECONDS")]
+        interval: Option<u64>,
+        #[command(flatten)]
+        output: OutputOptions,
+        /// The program to launch, followed by its arguments
+        #[arg(required = true, last = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+    /// List the processes that a process name would select, with their current CPU usage
+    List {
+        /// Only list the processes matching the pid or name, or any of several separated by commas,
+        /// except with the glob and regex match modes
+        filter: Option<String>,
+        #[command(flatten)]
+        selection: SelectionOptions,
+    },
+    /// Compare two saved captures and show what changed between them
+    Diff {
+        /// The baseline capture file, as written by --format json
+        baseline: PathBuf,
+        /// The capture file to compare against the baseline
+        comparison: PathBuf,
+        /// Open the output file using the optionally specified editor
+        #[arg(short = 'e', long = "edit")]
+        edit: Option<Option<String>>,
+        /// The format of the diff output
+        #[arg(short = 'f', long = "format", value_enum, default_value_t)]
+        format: DiffFormat,
+        /// Leave out call paths with less than the specified percentage of samples in both captures
+        #[arg(short = 'p', long = "min-percent", value_name = "PERCENT")]
+        min_percent: Option<f64>,
+    },
+}
+
+#[derive(Args, Debug)]
+struct SelectionOptions {
+    /// How process names are matched
+    #[arg(
+        short = 'm',
+        long = "match",
+        value_enum,
+        default_value_t,
+        value_name = "MODE"
+    )]
+    match_mode: process_selector::MatchMode,
+    /// Match against the full command line of the processes instead of their name
+    #[arg(long = "command-line")]
+    command_line: bool,
+    /// Pick the most recently started process when several match
+    #[arg(long = "newest", conflicts_with = "oldest")]
+    newest: bool,
+    /// Pick the earliest started process when several match
+    #[arg(long = "oldest")]
+    oldest: bool,
+    /// Only match processes running as the specified user, with or without domain
+    #[arg(long = "user", value_name = "USER")]
+    user: Option<String>,
+    /// Only match processes whose parent has the specified pid
+    #[arg(long = "parent", value_name = "PID")]
+    parent: Option<Pid>,
+}
+
+#[derive(Args, Clone, Debug)]
+struct OutputOptions {
     /// Open the output file using the optionally specified editor
     #[arg(short = 'e', long = "edit")]
     edit: Option<Option<String>>,
+    /// The format of the sample analysis output
+    #[arg(short = 'f', long = "format", value_enum, default_value_t)]
+    format: OutputFormat,
+    /// Only include the specified threads, can be repeated
+    #[arg(short = 't', long = "thread", value_name = "TID")]
+    threads: Vec<sampler::Tid>,
+    /// Invert the sample trees, showing the innermost frames first
+    #[arg(short = 'i', long = "invert")]
+    invert: bool,
+    /// Prune sample tree nodes with less than the specified percentage of a thread's samples
+    #[arg(short = 'p', long = "min-percent", value_name = "PERCENT")]
+    min_percent: Option<f64>,
+    /// Only include samples taken from the specified time into the capture, e.g. 2.5s or 250ms
+    #[arg(long = "from", value_name = "TIME", value_parser = parse_time)]
+    from: Option<Duration>,
+    /// Only include samples taken before the specified time into the capture
+    #[arg(long = "to", value_name = "TIME", value_parser = parse_time)]
+    to: Option<Duration>,
+    /// Split the capture into the specified number of equal time windows and list the top functions of each
+    #[arg(long = "windows", value_name = "COUNT", value_parser = clap::value_parser!(u32).range(1..))]
+    windows: Option<u32>,
+    /// Detect the phases of each thread where its dominant functions change, and list the top functions of each
+    #[arg(long = "phases", conflicts_with = "windows")]
+    phases: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum OutputFormat {
+    /// Indented call tree per thread
+    #[default]
+    Text,
+    /// Chrome trace event JSON timeline, viewable in Perfetto or chrome://tracing
+    ChromeTrace,
+    /// Self-contained HTML report with a collapsible call tree per thread
+    Html,
+    /// Versioned JSON representation of the complete capture
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum DiffFormat {
+    /// Per-function changes and merged call tree with +/- percentages
+    #[default]
+    Text,
+    /// Differential flame graph SVG, where red frames grew and blue frames shrank
+    FlameGraph,
+}
+
+impl OutputFormat {
+    fn file_extension(&self) -> &'static str {
+        match self {
+            Self::Text => "txt",
+            Self::ChromeTrace => "trace.json",
+            Self::Html => "html",
+            Self::Json => "json",
+        }
+    }
+}
+
+impl OutputOptions {
+    /// Whether the output needs the timeline of each thread rather than only its sample tree.
+    /// Saved captures keep them, so they can be sliced by time when reported later.
+    fn needs_timelines(&self) -> bool {
+        matches!(self.format, OutputFormat::ChromeTrace | OutputFormat::Json)
+            || self.from.is_some()
+            || self.to.is_some()
+            || self.windows.is_some()
+            || self.phases
+    }
+
+    /// Whether the output combines several processes sampled together into a single file, rather
+    /// than writing a file for each of them.
+    fn combines_processes(&self) -> bool {
+        matches!(self.format, OutputFormat::Text | OutputFormat::ChromeTrace)
+            && self.windows.is_none()
+            && !self.phases
+    }
 }
 
 fn main() -> ExitCode {
     let options = Options::parse();
-    let Some(pid) = Pid::from_str_radix(&options.process, 10)
-        .ok()
-        .or_else(|| pid_for_name(&options.process, options.wait))
-    else {
-        eprintln!("No such process - {}", options.process);
-        return ExitCode::FAILURE;
-    };
-
-    match sampler::profile(
-        pid,
-        Duration::from_secs(options.duration.unwrap_or(10)),
-        Duration::from_millis(options.interval.unwrap_or(1)),
-    ) {
-        Ok(process_sample) => {
-            let mut should_output = true;
-            if let Ok(file_path) = output_to_tmp_file(&process_sample) {
-                println!("Sample analysis written to file {}\n", file_path.display());
-                if let Some(edit) = options.edit {
-                    if open_editor(file_path, edit)
-                        .map_err(|e| println!("Unable to open editor: {e}"))
-                        .is_ok()
-                    {
-                        should_output = false;
+    match options.command {
+        Some(Command::Report { capture, output }) => {
+            return match report::json::load_capture(&capture) {
+                Ok(process_sample) => output_process_sample(process_sample, output),
+                Err(error) => {
+                    eprintln!("Failed to load {} - {}", capture.display(), error);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        Some(Command::Merge { captures, output }) => {
+            let mut process_samples = Vec::new();
+            for capture in captures {
+                match report::json::load_capture(&capture) {
+                    Ok(process_sample) => process_samples.push(process_sample),
+                    Err(error) => {
+                        eprintln!("Failed to load {} - {}", capture.display(), error);
+                        return ExitCode::FAILURE;
                     }
                 }
             }
-            if should_output {
-                println!("{}", process_sample);
+            let Some(merged) = report::merge_captures(&process_samples) else {
+                return ExitCode::FAILURE;
+            };
+            println!("Merged {} captures", merged.get_capture_count());
+            return output_process_sample(merged, output);
+        }
+        Some(Command::Run {
+            duration,
+            interval,
+            output,
+            command,
+        }) => {
+            return match sampler::profile_command(
+                &command,
+                duration.map_or(Duration::MAX, Duration::from_secs),
+                Duration::from_millis(interval.unwrap_or(1)),
+                output.needs_timelines(),
+            ) {
+                Ok(process_sample) => output_process_sample(process_sample, output),
+                Err(error) => {
+                    eprintln!("Failed to sample {} - {}", command.join(" "), error);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        Some(Command::Diff {
+            baseline,
+            comparison,
+            edit,
+            format,
+            min_percent,
+        }) => {
+            return match (
+                report::json::load_capture(&baseline),
+                report::json::load_capture(&comparison),
+            ) {
+                (Ok(baseline), Ok(comparison)) => {
+                    output_diff(&baseline, &comparison, edit, format, min_percent)
+                }
+                (Err(error), _) => {
+                    eprintln!("Failed to load {} - {}", baseline.display(), error);
+                    ExitCode::FAILURE
+                }
+                (_, Err(error)) => {
+                    eprintln!("Failed to load {} - {}", comparison.display(), error);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        Some(Command::List { filter, selection }) => {
+            return list_processes(filter.as_deref().unwrap_or_default(), &selection);
+        }
+        None => {}
+    }
+
+    let pids = if let Some(port) = options.port {
+        match resource_owner::pids_for_port(port) {
+            Ok(pids) if pids.is_empty() => {
+                eprintln!("No process is listening on port {}", port);
+                return ExitCode::FAILURE;
+            }
+            Ok(pids) => pids,
+            Err(error) => {
+                eprintln!(
+                    "Unable to find the processes using port {} - {}",
+                    port, error
+                );
+                return ExitCode::FAILURE;
+            }
+        }
+    } else if let Some(file) = &options.file {
+        match resource_owner::pids_for_file(file) {
+            Ok(pids) if pids.is_empty() => {
+                eprintln!("No process has {} open", file.display());
+                return ExitCode::FAILURE;
+            }
+            Ok(pids) => pids,
+            Err(error) => {
+                eprintln!(
+                    "Unable to find the processes using {} - {}",
+                    file.display(),
+                    error
+                );
+                return ExitCode::FAILURE;
             }
-            ExitCode::SUCCESS
         }
+    } else {
+        let mut pids = Vec::new();
+        let processes = options.process.unwrap_or_default();
+        for process in options.selection.match_mode.split_patterns(&processes) {
+            let pid = match Pid::from_str_radix(process, 10) {
+                Ok(pid) => pid,
+                Err(_) => match pid_for_name(process, &options.selection, options.wait) {
+                    Ok(pid) => pid,
+                    Err(error) => {
+                        eprintln!("{}", error);
+                        return ExitCode::FAILURE;
+                    }
+                },
+            };
+            if !pids.contains(&pid) {
+                pids.push(pid);
+            }
+        }
+        pids
+    };
+
+    let duration = Duration::from_secs(options.duration.or(options.duration_seconds).unwrap_or(10));
+    let interval = Duration::from_millis(
+        options
+            .interval
+            .or(options.interval_milliseconds)
+            .unwrap_or(1),
+    );
+    if pids.len() > 1 || options.tree {
+        return match sampler::profile_processes(
+            &pids,
+            options.tree,
+            duration,
+            interval,
+            options.output.needs_timelines(),
+        ) {
+            Ok(process_samples) => output_process_samples(process_samples, options.output),
+            Err(error) => {
+                eprintln!(
+                    "Failed to sample pids {} - {}",
+                    pids.iter()
+                        .map(Pid::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    error
+                );
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let pid = pids[0];
+    match sampler::profile(pid, duration, interval, options.output.needs_timelines()) {
+        Ok(process_sample) => output_process_sample(process_sample, options.output),
         Err(error) => {
             eprintln!("Failed to sample pid {} - {}", pid, error);
             ExitCode::FAILURE
@@ -72,14 +402,254 @@ fn main() -> ExitCode {
     }
 }
 
-fn pid_for_name(name: &str, wait: bool) -> Option<Pid> {
+fn output_process_sample(
+    mut process_sample: sampler::ProcessSample,
+    options: OutputOptions,
+) -> ExitCode {
+    if !options.threads.is_empty() {
+        process_sample.retain_threads(|thread_id| options.threads.contains(&thread_id));
+    }
+
+    let time_range = options.from.unwrap_or_default()
+        ..options
+            .to
+            .unwrap_or(Duration::MAX)
+            .min(process_sample.get_duration());
+    if options.from.is_some() || options.to.is_some() || options.windows.is_some() || options.phases
+    {
+        if process_sample.get_timelines().is_empty() {
+            eprintln!("The capture has no timelines to select samples by time from");
+            return ExitCode::FAILURE;
+        }
+        process_sample.slice(&time_range);
+    }
+    if let Some(window_count) = options.windows {
+        output_to_file_and_editor(
+            &process_sample,
+            "windows.txt",
+            &report::WindowSummary::new(&process_sample, time_range, window_count),
+            options.edit,
+            true,
+        );
+        return ExitCode::SUCCESS;
+    }
+    if options.phases {
+        output_to_file_and_editor(
+            &process_sample,
+            "phases.txt",
+            &report::PhaseReport::new(&process_sample),
+            options.edit,
+            true,
+        );
+        return ExitCode::SUCCESS;
+    }
+
+    if options.invert {
+        process_sample.invert();
+    }
+    if let Some(min_percent) = options.min_percent {
+        process_sample.prune(min_percent);
+    }
+
+    let output: Box<dyn std::fmt::Display> = match options.format {
+        OutputFormat::Text => Box::new(&process_sample),
+        OutputFormat::ChromeTrace => Box::new(report::ChromeTrace::new(&process_sample)),
+        OutputFormat::Html => Box::new(report::HtmlReport::new(&process_sample)),
+        OutputFormat::Json => Box::new(report::JsonReport::new(&process_sample)),
+    };
+    let should_output = matches!(options.format, OutputFormat::Text);
+    output_to_file_and_editor(
+        &process_sample,
+        options.format.file_extension(),
+        output.as_ref(),
+        options.edit,
+        should_output,
+    );
+    ExitCode::SUCCESS
+}
+
+/// Output several processes sampled together, combined into a single text report or trace.
+/// The other outputs, such as saved captures, are written to a file for each process.
+fn output_process_samples(
+    mut process_samples: Vec<sampler::ProcessSample>,
+    options: OutputOptions,
+) -> ExitCode {
+    if process_samples.len() == 1 {
+        return output_process_sample(process_samples.remove(0), options);
+    }
+    if !options.combines_processes() {
+        let mut exit_code = ExitCode::SUCCESS;
+        for process_sample in process_samples {
+            if output_process_sample(process_sample, options.clone()) != ExitCode::SUCCESS {
+                exit_code = ExitCode::FAILURE;
+            }
+        }
+        return exit_code;
+    }
+
+    for process_sample in &mut process_samples {
+        if !options.threads.is_empty() {
+            process_sample.retain_threads(|thread_id| options.threads.contains(&thread_id));
+        }
+        if options.from.is_some() || options.to.is_some() {
+            let time_range = options.from.unwrap_or_default()
+                ..options
+                    .to
+                    .unwrap_or(Duration::MAX)
+                    .min(process_sample.get_duration());
+            process_sample.slice(&time_range);
+        }
+        if options.invert {
+            process_sample.invert();
+        }
+        if let Some(min_percent) = options.min_percent {
+            process_sample.prune(min_percent);
+        }
+    }
+
+    let output: Box<dyn std::fmt::Display> = match options.format {
+        OutputFormat::ChromeTrace => Box::new(report::ChromeTrace::for_processes(&process_samples)),
+        _ => Box::new(report::ProcessGroupReport::new(&process_samples)),
+    };
+    let should_output = matches!(options.format, OutputFormat::Text);
+    output_to_file_and_editor(
+        &process_samples[0],
+        options.format.file_extension(),
+        output.as_ref(),
+        options.edit,
+        should_output,
+    );
+    ExitCode::SUCCESS
+}
+
+fn output_diff(
+    baseline: &sampler::ProcessSample,
+    comparison: &sampler::ProcessSample,
+    edit: Option<Option<String>>,
+    format: DiffFormat,
+    min_percent: Option<f64>,
+) -> ExitCode {
+    let profile_diff = report::ProfileDiff::new(baseline, comparison);
+    match format {
+        DiffFormat::Text => output_to_file_and_editor(
+            comparison,
+            "diff.txt",
+            &report::DiffReport::new(&profile_diff, min_percent.unwrap_or_default()),
+            edit,
+            true,
+        ),
+        DiffFormat::FlameGraph => output_to_file_and_editor(
+            comparison,
+            "diff.svg",
+            &report::DiffFlameGraph::new(&profile_diff),
+            edit,
+            false,
+        ),
+    }
+    ExitCode::SUCCESS
+}
+
+/// Write the output to a new temporary file, then either open it in the editor
+/// or print the output as well, when `should_output` is set.
+fn output_to_file_and_editor(
+    process_sample: &sampler::ProcessSample,
+    file_extension: &str,
+    output: &dyn std::fmt::Display,
+    edit: Option<Option<String>>,
+    mut should_output: bool,
+) {
+    if let Ok(file_path) = output_to_tmp_file(process_sample, file_extension, output) {
+        println!("Sample analysis written to file {}\n", file_path.display());
+        if let Some(edit) = edit {
+            if open_editor(file_path, edit)
+                .map_err(|e| println!("Unable to open editor: {e}"))
+                .is_ok()
+            {
+                should_output = false;
+            }
+        }
+    }
+    if should_output {
+        println!("{}", output);
+    }
+}
+
+/// Parse a time into the capture, in seconds unless it has an `s` or `ms` suffix.
+fn parse_time(time: &str) -> Result<Duration, String> {
+    let (value, unit) = if let Some(value) = time.strip_suffix("ms") {
+        (value, 0.001)
+    } else {
+        (time.strip_suffix('s').unwrap_or(time), 1.0)
+    };
+
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .and_then(|value| Duration::try_from_secs_f64(value * unit).ok())
+        .ok_or_else(|| format!("invalid time '{time}', expected e.g. 2.5s or 250ms"))
+}
+
+fn list_processes(filter: &str, selection: &SelectionOptions) -> ExitCode {
+    let mut processes: Vec<process_selector::ProcessDetails> = Vec::new();
+    for name in selection.match_mode.split_patterns(filter) {
+        let matches = match Pid::from_str_radix(name, 10) {
+            Ok(pid) => process_selector::ProcessSelector::for_pid(pid).matching_processes(),
+            Err(_) => match process_selector(name, selection) {
+                Ok(selector) => selector.matching_processes(),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    return ExitCode::FAILURE;
+                }
+            },
+        };
+        for process in matches {
+            if !processes.iter().any(|listed| listed.pid == process.pid) {
+                processes.push(process);
+            }
+        }
+    }
+    if processes.is_empty() {
+        eprintln!("No such process - {}", filter);
+        return ExitCode::FAILURE;
+    }
+
+    let cpu_usage = process_selector::cpu_usage(&processes, Duration::from_millis(500));
+    print!(
+        "{}",
+        process_selector::ProcessList::new(&processes, &cpu_usage)
+    );
+    ExitCode::SUCCESS
+}
+
+fn process_selector(
+    name: &str,
+    selection: &SelectionOptions,
+) -> Result<process_selector::ProcessSelector, String> {
+    let preference = if selection.newest {
+        Some(process_selector::Preference::Newest)
+    } else if selection.oldest {
+        Some(process_selector::Preference::Oldest)
+    } else {
+        None
+    };
+    process_selector::ProcessSelector::new(
+        name,
+        selection.match_mode,
+        selection.command_line,
+        selection.user.clone(),
+        selection.parent,
+        preference,
+    )
+    .map_err(|error| format!("Invalid pattern '{}' - {}", name, error))
+}
+
+fn pid_for_name(name: &str, selection: &SelectionOptions, wait: bool) -> Result<Pid, String> {
+    let selector = process_selector(name, selection)?;
+
     let mut is_waiting = false;
     let matches = loop {
-        let processes = process_iterator::ProcessIterator::snapshot()?;
-
-        let matches: Vec<(String, Pid)> = processes
-            .filter(|(process_name, _)| process_name.contains(name))
-            .collect();
+        let matches = selector.matching_processes();
         if matches.is_empty() && wait {
             if !is_waiting {
                 println!("Waiting for '{name}' to appear...");
@@ -91,39 +661,47 @@ fn pid_for_name(name: &str, wait: bool) -> Option<Pid> {
         }
     };
 
-    // TODO: Filter down further and let the user pick one if there are multiple matches.
-    Some(matches.first()?.1)
+    match matches.as_slice() {
+        [] => Err(format!("No such process - {}", name)),
+        [process] => Ok(process.pid),
+        _ => process_selector::choose_process(name, &matches),
+    }
 }
 
-fn output_to_tmp_file(process_sample: &sampler::ProcessSample) -> std::io::Result<PathBuf> {
-    let file_path = output_file_path(process_sample);
+fn output_to_tmp_file(
+    process_sample: &sampler::ProcessSample,
+    file_extension: &str,
+    output: &dyn std::fmt::Display,
+) -> std::io::Result<PathBuf> {
+    let file_path = output_file_path(process_sample, file_extension);
     let mut tmp_file = std::fs::File::create_new(&file_path)?;
-    write!(tmp_file, "{}", process_sample)?;
+    write!(tmp_file, "{}", output)?;
 
     Ok(file_path)
 }
 
-fn output_file_path(process_sample: &sampler::ProcessSample) -> PathBuf {
+fn output_file_path(process_sample: &sampler::ProcessSample, file_extension: &str) -> PathBuf {
     let name = process_sample
         .process_info
         .path
         .file_stem()
         .and_then(std::ffi::OsStr::to_str)
-        .map(str::to_string)
+        .map(|name| format!("{}_{}", name, process_sample.process_info.pid))
         .unwrap_or(process_sample.process_info.pid.to_string());
 
     let date_time = unsafe { GetLocalTime() };
 
     let mut tmp_file = std::env::temp_dir();
     tmp_file.push(format!(
-        "{}_{:04}-{:02}-{:02}_{:02}{:02}{:02}.spinsample.txt",
+        "{}_{:04}-{:02}-{:02}_{:02}{:02}{:02}.spinsample.{}",
         name,
         date_time.wYear,
         date_time.wMonth,
         date_time.wDay,
         date_time.wHour,
         date_time.wMinute,
-        date_time.wSecond
+        date_time.wSecond,
+        file_extension
     ));
 
     tmp_file
@@ -4,6 +4,15 @@ use windows::Win32::System::Diagnostics::ToolHelp::*;
 
 use super::*;
 
+/// A process as listed by a process snapshot.
+#[derive(Debug)]
+pub struct ProcessEntry {
+    pub name: String,
+    pub pid: Pid,
+    pub parent_pid: Pid,
+    pub thread_count: u32,
+}
+
 pub struct ProcessIterator {
     snapshot: Owned<HANDLE>,
     current: PROCESSENTRY32W,
@@ -23,7 +32,7 @@ impl ProcessIterator {
 }
 
 impl Iterator for ProcessIterator {
-    type Item = (String, Pid);
+    type Item = ProcessEntry;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.current.dwSize == 0 {
@@ -33,10 +42,18 @@ impl Iterator for ProcessIterator {
             unsafe { Process32NextW(*self.snapshot, &mut self.current).ok()? }
         }
 
-        let process_name = String::from_utf16(&self.current.szExeFile).unwrap_or_default();
-        let pid = self.current.th32ProcessID;
-
-        Some((process_name, pid))
+        let name_length = self
+            .current
+            .szExeFile
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(self.current.szExeFile.len());
+        Some(ProcessEntry {
+            name: String::from_utf16(&self.current.szExeFile[..name_length]).unwrap_or_default(),
+            pid: self.current.th32ProcessID,
+            parent_pid: self.current.th32ParentProcessID,
+            thread_count: self.current.cntThreads,
+        })
     }
 }
 
@@ -47,8 +64,8 @@ mod tests {
     #[test]
     fn test_process_iterator() {
         let snapshot = ProcessIterator::snapshot().expect("Failed to create process snapshot");
-        for (process_name, _pid) in snapshot {
-            assert!(!process_name.is_empty());
+        for process in snapshot {
+            assert!(!process.name.is_empty());
         }
     }
 }
@@ -0,0 +1,568 @@
+use std::ffi::c_void;
+use std::io::{BufRead, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use regex::Regex;
+use windows::core::{Owned, PWSTR};
+use windows::Wdk::System::Threading::{NtQueryInformationProcess, ProcessCommandLineInformation};
+use windows::Win32::Foundation::*;
+use windows::Win32::Security::*;
+use windows::Win32::System::Threading::*;
+
+use super::*;
+
+/// How a process name or command line is compared against the pattern given on the command line.
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum MatchMode {
+    /// Contains the pattern
+    #[default]
+    Contains,
+    /// Equal to the pattern, with or without the .exe extension
+    Exact,
+    /// Starts with the pattern
+    Prefix,
+    /// Matches the pattern, where * matches any characters and ? matches a single character
+    Glob,
+    /// Matches the regular expression anywhere
+    Regex,
+}
+
+impl MatchMode {
+    /// Split a list of patterns separated by commas. Glob and regular expression patterns are taken
+    /// whole, as commas are part of their syntax.
+    pub fn split_patterns(self, patterns: &str) -> Vec<&str> {
+        match self {
+            MatchMode::Contains | MatchMode::Exact | MatchMode::Prefix => {
+                patterns.split(',').collect()
+            }
+            MatchMode::Glob | MatchMode::Regex => vec![patterns],
+        }
+    }
+}
+
+/// Which process to pick when several processes match.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Preference {
+    Newest,
+    Oldest,
+}
+
+/// A running process, along with the details it can be selected by.
+#[derive(Debug, Default)]
+pub struct ProcessDetails {
+    pub pid: Pid,
+    pub parent_pid: Pid,
+    pub name: String,
+    pub command_line: Option<String>,
+    pub user: Option<String>,
+    pub thread_count: u32,
+    /// Creation time as a FILETIME value, to order processes by age.
+    pub creation_time: u64,
+    /// User and kernel CPU time used by the process so far.
+    pub cpu_time: Duration,
+}
+
+/// Selects the processes to sample by name or command line, and by other properties of the process.
+///
+/// Names and command lines are compared case-insensitively unless a regular expression is used.
+pub struct ProcessSelector {
+    pattern: Pattern,
+    match_command_line: bool,
+    pid: Option<Pid>,
+    user: Option<String>,
+    parent_pid: Option<Pid>,
+    preference: Option<Preference>,
+}
+
+enum Pattern {
+    Contains(String),
+    Exact(String),
+    Prefix(String),
+    Glob(String),
+    Regex(Regex),
+}
+
+impl ProcessSelector {
+    pub fn new(
+        pattern: &str,
+        match_mode: MatchMode,
+        match_command_line: bool,
+        user: Option<String>,
+        parent_pid: Option<Pid>,
+        preference: Option<Preference>,
+    ) -> Result<Self, regex::Error> {
+        let pattern = match match_mode {
+            MatchMode::Contains => Pattern::Contains(pattern.to_lowercase()),
+            MatchMode::Exact => Pattern::Exact(pattern.to_lowercase()),
+            MatchMode::Prefix => Pattern::Prefix(pattern.to_lowercase()),
+            MatchMode::Glob => Pattern::Glob(pattern.to_lowercase()),
+            MatchMode::Regex => Pattern::Regex(Regex::new(pattern)?),
+        };
+        Ok(Self {
+            pattern,
+            match_command_line,
+            pid: None,
+            user,
+            parent_pid,
+            preference,
+        })
+    }
+
+    /// Select all running processes.
+    pub fn all() -> Self {
+        Self {
+            pattern: Pattern::Contains(String::new()),
+            match_command_line: false,
+            pid: None,
+            user: None,
+            parent_pid: None,
+            preference: None,
+        }
+    }
+
+    /// Select the process with the specified pid, if it is running.
+    pub fn for_pid(pid: Pid) -> Self {
+        Self {
+            pid: Some(pid),
+            ..Self::all()
+        }
+    }
+
+    /// Whether a process could match judging by its name alone, so the other details are only
+    /// queried for the processes that could match.
+    fn matches_name(&self, name: &str) -> bool {
+        self.match_command_line || self.pattern.matches(name, true)
+    }
+
+    pub fn matches(&self, process: &ProcessDetails) -> bool {
+        let text_matches = if self.match_command_line {
+            process
+                .command_line
+                .as_deref()
+                .is_some_and(|command_line| self.pattern.matches(command_line, false))
+        } else {
+            self.pattern.matches(&process.name, true)
+        };
+        let user_matches = self.user.as_ref().is_none_or(|user| {
+            process.user.as_deref().is_some_and(|process_user| {
+                // Users may be given with or without their domain.
+                process_user.eq_ignore_ascii_case(user)
+                    || process_user
+                        .rsplit_once('\\')
+                        .is_some_and(|(_, name)| name.eq_ignore_ascii_case(user))
+            })
+        });
+        let pid_matches = self.pid.is_none_or(|pid| process.pid == pid);
+        let parent_matches = self
+            .parent_pid
+            .is_none_or(|parent_pid| process.parent_pid == parent_pid);
+        text_matches && user_matches && pid_matches && parent_matches
+    }
+
+    /// The running processes that match, ordered from the oldest to the newest, or only the newest
+    /// or oldest of them when a preference was given.
+    pub fn matching_processes(&self) -> Vec<ProcessDetails> {
+        let Some(processes) = process_iterator::ProcessIterator::snapshot() else {
+            return Vec::new();
+        };
+
+        let mut matches: Vec<ProcessDetails> = processes
+            .filter(|process| {
+                self.matches_name(&process.name)
+                    && self.pid.is_none_or(|pid| pid == process.pid)
+                    && self.parent_pid.is_none_or(|pid| pid == process.parent_pid)
+            })
+            .map(ProcessDetails::query)
+            .filter(|process| self.matches(process))
+            .collect();
+        matches.sort_by_key(|process| process.creation_time);
+
+        match self.preference {
+            Some(Preference::Newest) => matches.into_iter().next_back().into_iter().collect(),
+            Some(Preference::Oldest) => matches.into_iter().next().into_iter().collect(),
+            None => matches,
+        }
+    }
+}
+
+impl Pattern {
+    /// Match the text, where `is_name` allows exact matches of executable names without extension.
+    fn matches(&self, text: &str, is_name: bool) -> bool {
+        let text = match self {
+            Pattern::Regex(regex) => return regex.is_match(text),
+            _ => text.to_lowercase(),
+        };
+        match self {
+            Pattern::Contains(pattern) => text.contains(pattern.as_str()),
+            Pattern::Exact(pattern) => {
+                text == *pattern || (is_name && text.strip_suffix(".exe") == Some(pattern))
+            }
+            Pattern::Prefix(pattern) => text.starts_with(pattern.as_str()),
+            Pattern::Glob(pattern) => glob_matches(
+                &pattern.chars().collect::<Vec<_>>(),
+                &text.chars().collect::<Vec<_>>(),
+            ),
+            Pattern::Regex(_) => unreachable!(),
+        }
+    }
+}
+
+/// Match the whole text against a glob pattern, where `*` matches any sequence of characters
+/// and `?` matches any single character.
+fn glob_matches(pattern: &[char], text: &[char]) -> bool {
+    // Position after the last `*` in the pattern and the text position it was tried at,
+    // so a mismatch can retry with the `*` matching one more character.
+    let mut backtrack: Option<(usize, usize)> = None;
+    let (mut pattern_index, mut text_index) = (0, 0);
+    while text_index < text.len() {
+        match pattern.get(pattern_index) {
+            Some('*') => {
+                pattern_index += 1;
+                backtrack = Some((pattern_index, text_index));
+            }
+            Some(&c) if c == '?' || c == text[text_index] => {
+                pattern_index += 1;
+                text_index += 1;
+            }
+            _ => match backtrack {
+                Some((star_pattern_index, star_text_index)) => {
+                    pattern_index = star_pattern_index;
+                    text_index = star_text_index + 1;
+                    backtrack = Some((star_pattern_index, text_index));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[pattern_index..].iter().all(|&c| c == '*')
+}
+
+impl ProcessDetails {
+    /// Query the details of the specified process, leaving out the ones that aren't accessible.
+    pub fn query(entry: process_iterator::ProcessEntry) -> Self {
+        let pid = entry.pid;
+        let mut process = Self {
+            pid,
+            parent_pid: entry.parent_pid,
+            name: entry.name,
+            thread_count: entry.thread_count,
+            ..Default::default()
+        };
+        let Ok(process_handle) =
+            (unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) })
+        else {
+            return process;
+        };
+        let process_handle = unsafe { Owned::new(process_handle) };
+
+        process.command_line = unsafe { command_line(*process_handle) };
+        process.user = unsafe { user(*process_handle) };
+        (process.creation_time, process.cpu_time) = unsafe { process_times(*process_handle) };
+        process
+    }
+}
+
+/// Measure the CPU usage of each process over the interval, as a percentage of a single core.
+/// The usage is left out for the processes that can't be queried or exited in the meantime.
+pub fn cpu_usage(processes: &[ProcessDetails], interval: Duration) -> Vec<Option<f64>> {
+    let start_time = Instant::now();
+    std::thread::sleep(interval);
+    let elapsed = start_time.elapsed().as_secs_f64();
+
+    processes
+        .iter()
+        .map(|process| {
+            let process_handle =
+                unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process.pid) }
+                    .ok()?;
+            let process_handle = unsafe { Owned::new(process_handle) };
+            let (creation_time, cpu_time) = unsafe { process_times(*process_handle) };
+            // A different process may have been created with the same pid.
+            if creation_time != process.creation_time {
+                return None;
+            }
+            Some(cpu_time.saturating_sub(process.cpu_time).as_secs_f64() / elapsed * 100.0)
+        })
+        .collect()
+}
+
+/// The creation time of the specified process as a FILETIME value, when it can be queried.
+pub fn creation_time(pid: Pid) -> Option<u64> {
+    let process_handle =
+        unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }.ok()?;
+    let process_handle = unsafe { Owned::new(process_handle) };
+    Some(unsafe { process_times(*process_handle) }.0)
+}
+
+/// The creation time of the process as a FILETIME value, and its total CPU time.
+unsafe fn process_times(process_handle: HANDLE) -> (u64, Duration) {
+    let mut creation_time = FILETIME::default();
+    let mut exit_time = FILETIME::default();
+    let mut kernel_time = FILETIME::default();
+    let mut user_time = FILETIME::default();
+    _ = GetProcessTimes(
+        process_handle,
+        &mut creation_time,
+        &mut exit_time,
+        &mut kernel_time,
+        &mut user_time,
+    );
+
+    let value = |time: FILETIME| ((time.dwHighDateTime as u64) << 32) | (time.dwLowDateTime as u64);
+    // FILETIME values are in 100 nanosecond units.
+    let cpu_time = Duration::from_nanos((value(kernel_time) + value(user_time)) * 100);
+    (value(creation_time), cpu_time)
+}
+
+unsafe fn command_line(process_handle: HANDLE) -> Option<String> {
+    let mut size = 0;
+    _ = NtQueryInformationProcess(
+        process_handle,
+        ProcessCommandLineInformation,
+        std::ptr::null_mut(),
+        0,
+        &mut size,
+    );
+    // Use a u64 buffer so the UNICODE_STRING at its start is properly aligned.
+    let mut buffer = vec![0u64; (size as usize).div_ceil(size_of::<u64>())];
+    if NtQueryInformationProcess(
+        process_handle,
+        ProcessCommandLineInformation,
+        buffer.as_mut_ptr() as *mut c_void,
+        size,
+        &mut size,
+    ) != NTSTATUS(0)
+    {
+        return None;
+    }
+    // The string follows the UNICODE_STRING in the buffer, and isn't terminated by a NUL.
+    let command_line = &*(buffer.as_ptr() as *const UNICODE_STRING);
+    if command_line.Buffer.is_null() {
+        return None;
+    }
+    let characters = std::slice::from_raw_parts(
+        command_line.Buffer.as_ptr(),
+        command_line.Length as usize / size_of::<u16>(),
+    );
+    String::from_utf16(characters).ok()
+}
+
+unsafe fn user(process_handle: HANDLE) -> Option<String> {
+    let mut token = HANDLE::default();
+    OpenProcessToken(process_handle, TOKEN_QUERY, &mut token).ok()?;
+    let token = Owned::new(token);
+
+    let mut size = 0;
+    _ = GetTokenInformation(*token, TokenUser, None, 0, &mut size);
+    let mut buffer = vec![0u64; (size as usize).div_ceil(size_of::<u64>())];
+    GetTokenInformation(
+        *token,
+        TokenUser,
+        Some(buffer.as_mut_ptr() as *mut c_void),
+        size,
+        &mut size,
+    )
+    .ok()?;
+    let token_user = &*(buffer.as_ptr() as *const TOKEN_USER);
+
+    let mut name = [0u16; 256];
+    let mut name_length = name.len() as u32;
+    let mut domain = [0u16; 256];
+    let mut domain_length = domain.len() as u32;
+    let mut sid_name_use = SID_NAME_USE::default();
+    LookupAccountSidW(
+        None,
+        token_user.User.Sid,
+        Some(PWSTR::from_raw(name.as_mut_ptr())),
+        &mut name_length,
+        Some(PWSTR::from_raw(domain.as_mut_ptr())),
+        &mut domain_length,
+        &mut sid_name_use,
+    )
+    .ok()?;
+    Some(format!(
+        "{}\\{}",
+        String::from_utf16_lossy(&domain[..domain_length as usize]),
+        String::from_utf16_lossy(&name[..name_length as usize])
+    ))
+}
+
+/// Table of processes with their details and optionally measured CPU usage, as listed by the
+/// list command.
+pub struct ProcessList<'a> {
+    processes: &'a [ProcessDetails],
+    cpu_usage: &'a [Option<f64>],
+}
+
+impl<'a> ProcessList<'a> {
+    pub fn new(processes: &'a [ProcessDetails], cpu_usage: &'a [Option<f64>]) -> Self {
+        Self {
+            processes,
+            cpu_usage,
+        }
+    }
+}
+
+impl std::fmt::Display for ProcessList<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>8} {:>8} {:>7}  {:24} {:24} Command Line",
+            "Pid", "Threads", "CPU", "Name", "User"
+        )?;
+        for (index, process) in self.processes.iter().enumerate() {
+            let cpu_usage = match self.cpu_usage.get(index).copied().flatten() {
+                Some(cpu_usage) => format!("{:.1}%", cpu_usage),
+                None => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{:>8} {:>8} {:>7}  {:24} {:24} {}",
+                process.pid,
+                process.thread_count,
+                cpu_usage,
+                process.name,
+                process.user.as_deref().unwrap_or("-"),
+                process.command_line.as_deref().unwrap_or("-")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Pick one of several matching processes, asking the user when running interactively.
+/// Returns an error listing the processes when not running interactively.
+pub fn choose_process(pattern: &str, processes: &[ProcessDetails]) -> Result<Pid, String> {
+    let mut list = String::new();
+    for (index, process) in processes.iter().enumerate() {
+        list += &format!(
+            "  {:>3}) {:>8}  {:24} {:24} {}\n",
+            index + 1,
+            process.pid,
+            process.name,
+            process.user.as_deref().unwrap_or("{unknown}"),
+            process.command_line.as_deref().unwrap_or("")
+        );
+    }
+
+    if !std::io::stdin().is_terminal() {
+        return Err(format!(
+            "Multiple processes match '{}', specify a pid or a more specific selector:\n{}",
+            pattern,
+            list.trim_end()
+        ));
+    }
+
+    println!("Multiple processes match '{}':", pattern);
+    print!("{}", list);
+    loop {
+        print!("Select a process [1-{}]: ", processes.len());
+        _ = std::io::stdout().flush();
+
+        let mut line = String::new();
+        if std::io::stdin().lock().read_line(&mut line).unwrap_or(0) == 0 {
+            return Err(format!("No process selected for '{}'", pattern));
+        }
+        if let Some(process) = line
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| processes.get(index.checked_sub(1)?))
+        {
+            return Ok(process.pid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(name: &str, command_line: &str, user: &str) -> ProcessDetails {
+        ProcessDetails {
+            pid: 1,
+            name: name.to_string(),
+            command_line: Some(command_line.to_string()),
+            user: Some(user.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_match_modes() {
+        let selector = |pattern, match_mode| {
+            ProcessSelector::new(pattern, match_mode, false, None, None, None).unwrap()
+        };
+        let server = process("Server.exe", "server.exe --port 80", "HOST\\alice");
+
+        assert!(selector("serv", MatchMode::Contains).matches(&server));
+        assert!(selector("server", MatchMode::Exact).matches(&server));
+        assert!(!selector("serv", MatchMode::Exact).matches(&server));
+        assert!(selector("SERV", MatchMode::Prefix).matches(&server));
+        assert!(!selector("erv", MatchMode::Prefix).matches(&server));
+        assert!(selector("s*r.e?e", MatchMode::Glob).matches(&server));
+        assert!(!selector("s*r", MatchMode::Glob).matches(&server));
+        assert!(selector("caf?.exe", MatchMode::Glob).matches(&process("Café.exe", "", "")));
+        assert!(selector("^Serv.r\\.exe$", MatchMode::Regex).matches(&server));
+    }
+
+    #[test]
+    fn test_commas_kept_in_glob_and_regex_patterns() {
+        assert_eq!(
+            MatchMode::Contains.split_patterns("server,1234"),
+            vec!["server", "1234"]
+        );
+        assert_eq!(MatchMode::Glob.split_patterns("a,b*"), vec!["a,b*"]);
+        assert_eq!(
+            MatchMode::Regex.split_patterns("worker{1,3}"),
+            vec!["worker{1,3}"]
+        );
+    }
+
+    #[test]
+    fn test_command_line_and_user_selectors() {
+        let server = process("server.exe", "server.exe --port 80", "HOST\\alice");
+
+        let selector = ProcessSelector::new(
+            "*--port 80",
+            MatchMode::Glob,
+            true,
+            Some("ALICE".to_string()),
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(selector.matches(&server));
+
+        let selector = ProcessSelector::new(
+            "server",
+            MatchMode::Contains,
+            false,
+            Some("HOST\\bob".to_string()),
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(!selector.matches(&server));
+    }
+
+    #[test]
+    fn test_process_list_columns() {
+        let mut server = process("server.exe", "server.exe --port 80", "HOST\\alice");
+        server.thread_count = 12;
+        let processes = [server, process("idle.exe", "idle.exe", "HOST\\bob")];
+
+        let list = ProcessList::new(&processes, &[Some(12.345), None]).to_string();
+
+        assert_eq!(
+            list.lines().nth(1),
+            Some("       1       12   12.3%  server.exe               HOST\\alice               server.exe --port 80")
+        );
+        assert!(list
+            .lines()
+            .nth(2)
+            .unwrap()
+            .contains("       0       -  idle.exe"));
+    }
+}
@@ -0,0 +1,23 @@
+mod chrome_trace;
+mod diff;
+mod error;
+mod html;
+pub mod json;
+mod merge;
+mod phases;
+mod process_group;
+#[cfg(test)]
+mod test_samples;
+mod top_functions;
+mod windows;
+
+pub use chrome_trace::ChromeTrace;
+pub use diff::{DiffFlameGraph, DiffReport, ProfileDiff};
+pub use error::Error;
+pub use html::HtmlReport;
+pub use json::JsonReport;
+pub use merge::merge_captures;
+pub use phases::PhaseReport;
+pub use process_group::ProcessGroupReport;
+pub use top_functions::top_functions;
+pub use windows::WindowSummary;
@@ -0,0 +1,295 @@
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::sampler::{ProcessSample, SymbolInfo, ThreadTimeline, Tid};
+
+/// Chrome trace event representation of a process sample, viewable in Perfetto or chrome://tracing.
+///
+/// Consecutive samples of a thread that share a common stack prefix are merged into nested
+/// duration events, showing what each thread was doing over the course of the capture.
+pub struct ChromeTrace<'a> {
+    process_samples: &'a [ProcessSample],
+}
+
+impl<'a> ChromeTrace<'a> {
+    pub fn new(process_sample: &'a ProcessSample) -> Self {
+        Self {
+            process_samples: std::slice::from_ref(process_sample),
+        }
+    }
+
+    /// A single trace of several processes sampled together, on their shared timeline.
+    pub fn for_processes(process_samples: &'a [ProcessSample]) -> Self {
+        Self { process_samples }
+    }
+
+    fn thread_events(
+        &self,
+        process_sample: &ProcessSample,
+        timeline: &ThreadTimeline,
+    ) -> Vec<Value> {
+        let thread_id = timeline.get_thread_id();
+        let mut events = vec![json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": process_sample.process_info.pid,
+            "tid": thread_id,
+            "args": { "name": format!("Thread {}", thread_id) },
+        })];
+
+        let stack_table = process_sample.get_stack_table();
+        // Frames of the current stack that are still running, along with the time they started.
+        let mut open_frames: Vec<(u64, Duration)> = Vec::new();
+        for sample in timeline.get_samples() {
+            let backtrace = stack_table.backtrace(sample.get_stack_id());
+            let common_frames = open_frames
+                .iter()
+                .zip(&backtrace)
+                .take_while(|((open_address, _), address)| open_address == *address)
+                .count();
+
+            while open_frames.len() > common_frames {
+                let (address, start) = open_frames.pop().unwrap();
+                events.push(self.duration_event(
+                    process_sample,
+                    thread_id,
+                    address,
+                    start,
+                    sample.get_timestamp(),
+                ));
+            }
+            open_frames.extend(
+                backtrace[common_frames..]
+                    .iter()
+                    .map(|&address| (address, sample.get_timestamp())),
+            );
+        }
+
+        // The last sample is assumed to last for one sampling interval.
+        let end = timeline
+            .get_samples()
+            .last()
+            .map(|sample| sample.get_timestamp() + process_sample.get_interval())
+            .unwrap_or_default();
+        while let Some((address, start)) = open_frames.pop() {
+            events.push(self.duration_event(process_sample, thread_id, address, start, end));
+        }
+
+        events.extend(self.cpu_usage_events(process_sample, timeline));
+        events
+    }
+
+    /// Counter events with the CPU usage of the thread between each pair of consecutive samples.
+    fn cpu_usage_events<'b>(
+        &'b self,
+        process_sample: &'b ProcessSample,
+        timeline: &'b ThreadTimeline,
+    ) -> impl Iterator<Item = Value> + 'b {
+        timeline.get_samples().windows(2).filter_map(move |pair| {
+            let elapsed = pair[1]
+                .get_timestamp()
+                .checked_sub(pair[0].get_timestamp())?;
+            if elapsed.is_zero() {
+                return None;
+            }
+            let cpu_time = (pair[1].get_user_cpu_time() + pair[1].get_kernel_cpu_time())
+                .saturating_sub(pair[0].get_user_cpu_time() + pair[0].get_kernel_cpu_time());
+
+            Some(json!({
+                "name": format!("Thread {} CPU %", timeline.get_thread_id()),
+                "ph": "C",
+                "ts": pair[0].get_timestamp().as_micros() as u64,
+                "pid": process_sample.process_info.pid,
+                "tid": timeline.get_thread_id(),
+                "args": { "cpu": 100.0 * cpu_time.as_secs_f64() / elapsed.as_secs_f64() },
+            }))
+        })
+    }
+
+    fn duration_event(
+        &self,
+        process_sample: &ProcessSample,
+        thread_id: Tid,
+        address: u64,
+        start: Duration,
+        end: Duration,
+    ) -> Value {
+        let symbol = process_sample.get_symbol_table().symbol(address);
+
+        let name = symbol
+            .and_then(SymbolInfo::get_function)
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{:#x}", address));
+        let module_name = symbol
+            .and_then(SymbolInfo::get_module_name)
+            .unwrap_or("{unknown}");
+
+        json!({
+            "name": name,
+            "cat": module_name,
+            "ph": "X",
+            "ts": start.as_micros() as u64,
+            "dur": (end - start).as_micros() as u64,
+            "pid": process_sample.process_info.pid,
+            "tid": thread_id,
+            "args": { "module": module_name, "address": format!("{:#x}", address) },
+        })
+    }
+}
+
+impl std::fmt::Display for ChromeTrace<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut events = Vec::new();
+        for process_sample in self.process_samples {
+            let process_info = &process_sample.process_info;
+            events.push(json!({
+                "name": "process_name",
+                "ph": "M",
+                "pid": process_info.pid,
+                "args": { "name": process_info.path.to_string_lossy() },
+            }));
+            for timeline in process_sample.get_timelines() {
+                events.append(&mut self.thread_events(process_sample, timeline));
+            }
+        }
+
+        write!(
+            f,
+            "{}",
+            json!({
+                "traceEvents": events,
+                "displayTimeUnit": "ms",
+            })
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::test_samples::{test_backtrace_sample, test_process_sample};
+    use crate::sampler::{StackTable, SymbolTable, TimelineSample};
+
+    fn duration_events(process_sample: &ProcessSample) -> Vec<(String, u64, u64)> {
+        let trace: Value = serde_json::from_str(&ChromeTrace::new(process_sample).to_string())
+            .expect("Failed to parse trace");
+        trace["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|event| event["ph"] == "X")
+            .map(|event| {
+                (
+                    event["name"].as_str().unwrap().to_string(),
+                    event["ts"].as_u64().unwrap(),
+                    event["dur"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_common_prefix_is_merged() {
+        let process_sample = test_backtrace_sample(&[&[&[1, 2], &[1, 2], &[1, 3]]], &[]);
+
+        assert_eq!(
+            duration_events(&process_sample),
+            vec![
+                ("0x2".to_string(), 0, 2000),
+                ("0x3".to_string(), 2000, 1000),
+                ("0x1".to_string(), 0, 3000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diverging_stacks_are_split() {
+        let process_sample = test_backtrace_sample(&[&[&[1, 2], &[3, 2]]], &[]);
+
+        assert_eq!(
+            duration_events(&process_sample),
+            vec![
+                ("0x2".to_string(), 0, 1000),
+                ("0x1".to_string(), 0, 1000),
+                ("0x2".to_string(), 1000, 1000),
+                ("0x3".to_string(), 1000, 1000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_events_placed_at_sample_timestamps() {
+        // A thread that started 5 ms into the capture, with a slow snapshot between its samples.
+        let mut stack_table = StackTable::new();
+        let mut timeline = ThreadTimeline::new(2);
+        for (tick, timestamp, backtrace) in [(5, 5, [1, 2]), (6, 9, [1, 3])] {
+            timeline.add_sample(TimelineSample::new(
+                tick,
+                Duration::from_millis(timestamp),
+                Duration::default(),
+                Duration::default(),
+                stack_table.intern(backtrace),
+            ));
+        }
+        let process_sample =
+            test_process_sample(Vec::new(), vec![timeline], stack_table, SymbolTable::new());
+
+        assert_eq!(
+            duration_events(&process_sample),
+            vec![
+                ("0x2".to_string(), 5000, 4000),
+                ("0x3".to_string(), 9000, 1000),
+                ("0x1".to_string(), 5000, 5000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cpu_usage_between_samples() {
+        let process_sample = test_backtrace_sample(&[&[]], &[]);
+        let mut timeline = ThreadTimeline::new(2);
+        for (timestamp, cpu_time) in [(0, 0), (2, 1), (4, 3)] {
+            timeline.add_sample(TimelineSample::new(
+                0,
+                Duration::from_millis(timestamp),
+                Duration::from_millis(cpu_time),
+                Duration::default(),
+                None,
+            ));
+        }
+        let chrome_trace = ChromeTrace::new(&process_sample);
+
+        let cpu_usage: Vec<(u64, f64)> = chrome_trace
+            .cpu_usage_events(&process_sample, &timeline)
+            .map(|event| {
+                (
+                    event["ts"].as_u64().unwrap(),
+                    event["args"]["cpu"].as_f64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(cpu_usage, vec![(0, 50.0), (2000, 100.0)]);
+    }
+
+    #[test]
+    fn test_processes_share_trace() {
+        let process_samples = [test_backtrace_sample(&[&[&[1]]], &[]), {
+            let mut process_sample = test_backtrace_sample(&[&[&[2]]], &[]);
+            process_sample.process_info.pid = 3;
+            process_sample
+        }];
+
+        let trace: Value =
+            serde_json::from_str(&ChromeTrace::for_processes(&process_samples).to_string())
+                .expect("Failed to parse trace");
+        let pids: Vec<u64> = trace["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|event| event["ph"] == "X")
+            .map(|event| event["pid"].as_u64().unwrap())
+            .collect();
+        assert_eq!(pids, vec![1, 3]);
+    }
+}
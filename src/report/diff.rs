@@ -0,0 +1,431 @@
+use std::collections::HashMap;
+
+use super::html::escape_html;
+use super::top_functions::{percentage, top_functions, FunctionStats};
+use crate::sampler::{ProcessSample, SymbolInfo};
+
+const BASELINE: usize = 0;
+const COMPARISON: usize = 1;
+
+/// Maximum number of rows in the function deltas table.
+const FUNCTION_DELTAS_LIMIT: usize = 100;
+
+/// Differential profile of two captures, typically of two builds of the same program.
+///
+/// The sample trees of all threads are merged into a single call tree per capture, where frames
+/// are identified by function and module name rather than address, so captures of different
+/// builds, or with different module load addresses, line up. All counts are normalized to
+/// percentages of each capture's total sample count.
+pub struct ProfileDiff {
+    baseline_name: String,
+    comparison_name: String,
+    totals: [u32; 2],
+    /// Sample counts of each function in each capture.
+    function_stats: [Vec<FunctionStats>; 2],
+    /// Merged call tree of both captures, where the first node is the root.
+    nodes: Vec<DiffNode>,
+}
+
+struct DiffNode {
+    function: String,
+    module: String,
+    counts: [u32; 2],
+    children: Vec<usize>,
+}
+
+/// Change of a single function between the captures, as percentages of the total sample count.
+pub struct FunctionDelta {
+    pub function: String,
+    pub module: String,
+    pub self_percent: [f64; 2],
+    pub total_percent: [f64; 2],
+}
+
+impl ProfileDiff {
+    pub fn new(baseline: &ProcessSample, comparison: &ProcessSample) -> Self {
+        let mut profile_diff = Self {
+            baseline_name: process_name(baseline),
+            comparison_name: process_name(comparison),
+            totals: [0, 0],
+            function_stats: [top_functions(baseline), top_functions(comparison)],
+            nodes: vec![DiffNode {
+                function: String::new(),
+                module: String::new(),
+                counts: [0, 0],
+                children: Vec::new(),
+            }],
+        };
+        profile_diff.add_process_sample(BASELINE, baseline);
+        profile_diff.add_process_sample(COMPARISON, comparison);
+        profile_diff
+    }
+
+    fn add_process_sample(&mut self, side: usize, process_sample: &ProcessSample) {
+        let symbol_table = process_sample.get_symbol_table();
+        for thread in process_sample.get_threads() {
+            self.totals[side] += thread.get_sample_count();
+            self.nodes[0].counts[side] += thread.get_sample_count();
+
+            // Merged node indices of the current sample point's ancestors, starting with the root.
+            let mut path: Vec<usize> = vec![0];
+            for sample_point in thread.sample_tree_dfs_iter() {
+                path.truncate(sample_point.get_level() as usize);
+                let parent = *path.last().unwrap();
+
+                let symbol = symbol_table.symbol(sample_point.get_address());
+                let function = symbol
+                    .and_then(SymbolInfo::get_function)
+                    .unwrap_or("{unknown}");
+                let module = symbol
+                    .and_then(SymbolInfo::get_module_name)
+                    .unwrap_or("{unknown}");
+
+                let node = self.child_node(parent, function, module);
+                self.nodes[node].counts[side] += sample_point.get_count();
+                path.push(node);
+            }
+        }
+    }
+
+    fn child_node(&mut self, parent: usize, function: &str, module: &str) -> usize {
+        if let Some(&child) = self.nodes[parent].children.iter().find(|&&child| {
+            self.nodes[child].function == function && self.nodes[child].module == module
+        }) {
+            return child;
+        }
+
+        self.nodes.push(DiffNode {
+            function: function.to_string(),
+            module: module.to_string(),
+            counts: [0, 0],
+            children: Vec::new(),
+        });
+        let child = self.nodes.len() - 1;
+        self.nodes[parent].children.push(child);
+        child
+    }
+
+    fn percent(&self, side: usize, count: u32) -> f64 {
+        percentage(count, self.totals[side])
+    }
+
+    fn node_percent(&self, node: usize) -> [f64; 2] {
+        [
+            self.percent(BASELINE, self.nodes[node].counts[BASELINE]),
+            self.percent(COMPARISON, self.nodes[node].counts[COMPARISON]),
+        ]
+    }
+
+    /// Per-function changes, counted as by `top_functions` in each capture, ordered by the largest
+    /// change in self percentage first.
+    pub fn function_deltas(&self) -> Vec<FunctionDelta> {
+        let mut function_deltas: HashMap<(&str, &str), FunctionDelta> = HashMap::new();
+        for side in [BASELINE, COMPARISON] {
+            for stats in &self.function_stats[side] {
+                let function_delta = function_deltas
+                    .entry((stats.function.as_str(), stats.module.as_str()))
+                    .or_insert_with(|| FunctionDelta {
+                        function: stats.function.clone(),
+                        module: stats.module.clone(),
+                        self_percent: [0.0; 2],
+                        total_percent: [0.0; 2],
+                    });
+                function_delta.self_percent[side] = self.percent(side, stats.self_count);
+                function_delta.total_percent[side] = self.percent(side, stats.total_count);
+            }
+        }
+
+        let mut function_deltas: Vec<FunctionDelta> = function_deltas.into_values().collect();
+        function_deltas.sort_by(|a, b| {
+            b.self_delta()
+                .abs()
+                .total_cmp(&a.self_delta().abs())
+                .then(b.total_delta().abs().total_cmp(&a.total_delta().abs()))
+                .then(a.function.cmp(&b.function))
+        });
+        function_deltas
+    }
+}
+
+impl FunctionDelta {
+    pub fn self_delta(&self) -> f64 {
+        self.self_percent[COMPARISON] - self.self_percent[BASELINE]
+    }
+
+    pub fn total_delta(&self) -> f64 {
+        self.total_percent[COMPARISON] - self.total_percent[BASELINE]
+    }
+}
+
+/// Text representation of a profile diff, with per-function deltas and the merged call tree.
+pub struct DiffReport<'a> {
+    profile_diff: &'a ProfileDiff,
+    min_percent: f64,
+}
+
+impl<'a> DiffReport<'a> {
+    /// Call paths where neither capture reaches `min_percent` of its samples are left out.
+    pub fn new(profile_diff: &'a ProfileDiff, min_percent: f64) -> Self {
+        Self {
+            profile_diff,
+            min_percent,
+        }
+    }
+
+    fn write_call_tree(
+        &self,
+        f: &mut std::fmt::Formatter,
+        node: usize,
+        level: usize,
+    ) -> std::fmt::Result {
+        let percent = self.profile_diff.node_percent(node);
+        if percent[BASELINE] < self.min_percent && percent[COMPARISON] < self.min_percent {
+            return Ok(());
+        }
+
+        let diff_node = &self.profile_diff.nodes[node];
+        writeln!(
+            f,
+            " {}{:+.1}%  {:.1}% -> {:.1}%  {}  (in {})",
+            " ".repeat(level),
+            percent[COMPARISON] - percent[BASELINE],
+            percent[BASELINE],
+            percent[COMPARISON],
+            diff_node.function,
+            diff_node.module
+        )?;
+        for &child in &diff_node.children {
+            self.write_call_tree(f, child, level + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for DiffReport<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let profile_diff = self.profile_diff;
+        writeln!(
+            f,
+            "Baseline:   {}  ({} samples)",
+            profile_diff.baseline_name, profile_diff.totals[BASELINE]
+        )?;
+        writeln!(
+            f,
+            "Comparison: {}  ({} samples)",
+            profile_diff.comparison_name, profile_diff.totals[COMPARISON]
+        )?;
+
+        writeln!(f)?;
+        writeln!(f, "Functions:  self% change (baseline -> comparison), total% change (baseline -> comparison)")?;
+        for function_delta in profile_diff
+            .function_deltas()
+            .iter()
+            .filter(|delta| delta.self_delta() != 0.0 || delta.total_delta() != 0.0)
+            .take(FUNCTION_DELTAS_LIMIT)
+        {
+            writeln!(
+                f,
+                "  {:+6.1}% ({:.1}% -> {:.1}%)  {:+6.1}% ({:.1}% -> {:.1}%)  {}  (in {})",
+                function_delta.self_delta(),
+                function_delta.self_percent[BASELINE],
+                function_delta.self_percent[COMPARISON],
+                function_delta.total_delta(),
+                function_delta.total_percent[BASELINE],
+                function_delta.total_percent[COMPARISON],
+                function_delta.function,
+                function_delta.module
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Call tree:  change (baseline -> comparison)")?;
+        for &child in &profile_diff.nodes[0].children {
+            self.write_call_tree(f, child, 0)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Differential flame graph of a profile diff as a standalone SVG image.
+///
+/// Frame widths are proportional to the comparison capture, while the color shows the change
+/// relative to the baseline: red frames grew and blue frames shrank, with the saturation
+/// proportional to the size of the change.
+pub struct DiffFlameGraph<'a> {
+    profile_diff: &'a ProfileDiff,
+}
+
+const FLAME_GRAPH_WIDTH: f64 = 1200.0;
+const FRAME_HEIGHT: f64 = 16.0;
+const FONT_WIDTH: f64 = 7.0;
+
+impl<'a> DiffFlameGraph<'a> {
+    pub fn new(profile_diff: &'a ProfileDiff) -> Self {
+        Self { profile_diff }
+    }
+
+    fn depth(&self, node: usize) -> usize {
+        self.profile_diff.nodes[node]
+            .children
+            .iter()
+            .map(|&child| self.depth(child) + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn max_delta(&self) -> f64 {
+        (0..self.profile_diff.nodes.len())
+            .map(|node| {
+                let percent = self.profile_diff.node_percent(node);
+                (percent[COMPARISON] - percent[BASELINE]).abs()
+            })
+            .fold(0.0, f64::max)
+    }
+
+    fn write_frame(
+        &self,
+        f: &mut std::fmt::Formatter,
+        node: usize,
+        x: f64,
+        level: usize,
+        height: f64,
+        max_delta: f64,
+    ) -> std::fmt::Result {
+        let profile_diff = self.profile_diff;
+        let diff_node = &profile_diff.nodes[node];
+        let percent = profile_diff.node_percent(node);
+        let width = percent[COMPARISON] * FLAME_GRAPH_WIDTH / 100.0;
+        if width < 0.1 {
+            return Ok(());
+        }
+
+        let delta = percent[COMPARISON] - percent[BASELINE];
+        let intensity = if max_delta > 0.0 {
+            (delta.abs() / max_delta * 200.0) as u8
+        } else {
+            0
+        };
+        let color = if delta > 0.0 {
+            format!("rgb(255,{0},{0})", 255 - intensity)
+        } else {
+            format!("rgb({0},{0},255)", 255 - intensity)
+        };
+        let name = if node == 0 {
+            "all".to_string()
+        } else {
+            format!("{} (in {})", diff_node.function, diff_node.module)
+        };
+        let y = height - (level + 1) as f64 * FRAME_HEIGHT;
+
+        writeln!(
+            f,
+            "<g><title>{} {:.2}% -> {:.2}% ({:+.2}%)</title><rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\" stroke=\"#ccc\" stroke-width=\"0.5\"/>",
+            escape_html(&name),
+            percent[BASELINE],
+            percent[COMPARISON],
+            delta,
+            x,
+            y,
+            width,
+            FRAME_HEIGHT,
+            color
+        )?;
+        let max_chars = (width / FONT_WIDTH) as usize;
+        if max_chars >= 3 {
+            let label: String = if name.chars().count() > max_chars {
+                name.chars()
+                    .take(max_chars - 2)
+                    .chain("..".chars())
+                    .collect()
+            } else {
+                name
+            };
+            writeln!(
+                f,
+                "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+                x + 3.0,
+                y + FRAME_HEIGHT - 4.0,
+                escape_html(&label)
+            )?;
+        }
+        writeln!(f, "</g>")?;
+
+        let mut child_x = x;
+        for &child in &diff_node.children {
+            self.write_frame(f, child, child_x, level + 1, height, max_delta)?;
+            child_x += profile_diff.node_percent(child)[COMPARISON] * FLAME_GRAPH_WIDTH / 100.0;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for DiffFlameGraph<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let height = (self.depth(0) + 1) as f64 * FRAME_HEIGHT + 2.0 * FRAME_HEIGHT;
+        writeln!(
+            f,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"monospace\" font-size=\"11\">",
+            FLAME_GRAPH_WIDTH, height
+        )?;
+        writeln!(
+            f,
+            "<text x=\"4\" y=\"{}\">{} -> {}</text>",
+            FRAME_HEIGHT,
+            escape_html(&self.profile_diff.baseline_name),
+            escape_html(&self.profile_diff.comparison_name)
+        )?;
+        self.write_frame(f, 0, 0.0, 0, height, self.max_delta())?;
+        writeln!(f, "</svg>")
+    }
+}
+
+fn process_name(process_sample: &ProcessSample) -> String {
+    format!(
+        "{} - {}",
+        process_sample.process_info.pid,
+        process_sample.process_info.path.to_string_lossy()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::test_samples::test_backtrace_sample;
+
+    #[test]
+    fn test_frames_aligned_by_symbol() {
+        // Same functions at different addresses, with `work` taking a larger share of the samples.
+        let baseline = test_backtrace_sample(
+            &[&[&[1, 2], &[1, 3], &[1, 3], &[1, 3]]],
+            &[(1, "main"), (2, "work"), (3, "idle")],
+        );
+        let comparison = test_backtrace_sample(
+            &[&[&[10, 20], &[10, 20], &[10, 30], &[10, 30]]],
+            &[(10, "main"), (20, "work"), (30, "idle")],
+        );
+
+        let profile_diff = ProfileDiff::new(&baseline, &comparison);
+        assert_eq!(profile_diff.nodes.len(), 4);
+
+        let function_deltas = profile_diff.function_deltas();
+        let deltas: Vec<(&str, f64, f64)> = function_deltas
+            .iter()
+            .map(|delta| {
+                (
+                    delta.function.as_str(),
+                    delta.self_delta(),
+                    delta.total_delta(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            deltas,
+            vec![
+                ("idle", -25.0, -25.0),
+                ("work", 25.0, 25.0),
+                ("main", 0.0, 0.0)
+            ]
+        );
+    }
+}
@@ -0,0 +1,37 @@
+#[derive(Debug)]
+pub enum Error {
+    ReadCaptureFailed(std::io::Error),
+    ParseCaptureFailed(serde_json::Error),
+    UnsupportedCapture(String, u32),
+    InvalidCapture(String),
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ReadCaptureFailed(inner_error) => Some(inner_error),
+            Self::ParseCaptureFailed(inner_error) => Some(inner_error),
+            Self::UnsupportedCapture(_, _) => None,
+            Self::InvalidCapture(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::ReadCaptureFailed(inner_error) => {
+                write!(f, "unable to read capture: {}", inner_error)
+            }
+            Self::ParseCaptureFailed(inner_error) => {
+                write!(f, "unable to parse capture: {}", inner_error)
+            }
+            Self::UnsupportedCapture(format, version) => write!(
+                f,
+                "unsupported capture format '{}' version {}",
+                format, version
+            ),
+            Self::InvalidCapture(reason) => write!(f, "invalid capture: {}", reason),
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use super::top_functions::percentage;
+use super::*;
+use crate::sampler::{ProcessSample, SymbolInfo, ThreadSample};
+
+/// Maximum number of rows in the top functions table.
+const TOP_FUNCTIONS_LIMIT: usize = 100;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; font-size: 14px; margin: 2em; }
+h1 { font-size: 1.4em; }
+h2 { font-size: 1.2em; margin-top: 2em; }
+table { border-collapse: collapse; }
+th, td { padding: 2px 12px; text-align: left; }
+th { border-bottom: 1px solid #888; }
+td.count { text-align: right; }
+tr:nth-child(even) { background: #f4f4f4; }
+.tree { font-family: monospace; white-space: nowrap; }
+.tree details { margin-left: 1.2em; }
+.tree details.thread { margin-left: 0; margin-bottom: 0.5em; }
+.tree div.frame { margin-left: 2.4em; }
+.tree summary.thread { font-family: sans-serif; font-weight: bold; }
+.tree .module { color: #777; }
+.tree .address { color: #aaa; }
+.match { background: #ffe066; }
+#search { width: 30em; padding: 4px; }
+"#;
+
+const SCRIPT: &str = r#"
+document.getElementById("search").addEventListener("input", function (event) {
+    var query = event.target.value.toLowerCase();
+    document.querySelectorAll(".frame").forEach(function (frame) {
+        var isMatch = query.length > 0 && frame.textContent.toLowerCase().includes(query);
+        frame.classList.toggle("match", isMatch);
+        for (var node = frame.parentElement; isMatch && node; node = node.parentElement) {
+            if (node.tagName === "DETAILS") {
+                node.open = true;
+            }
+        }
+    });
+});
+"#;
+
+/// Self-contained HTML representation of a process sample, with a collapsible sample tree per thread.
+pub struct HtmlReport<'a> {
+    process_sample: &'a ProcessSample,
+}
+
+impl<'a> HtmlReport<'a> {
+    pub fn new(process_sample: &'a ProcessSample) -> Self {
+        Self { process_sample }
+    }
+
+    fn write_summary(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let process_info = &self.process_sample.process_info;
+        let user_cpu_time = process_info.user_cpu_time;
+        let kernel_cpu_time = process_info.kernel_cpu_time;
+        let total_cpu_time = user_cpu_time + kernel_cpu_time;
+
+        writeln!(
+            f,
+            "<h1>Process: {} - {}</h1>",
+            process_info.pid,
+            escape_html(&process_info.path.to_string_lossy())
+        )?;
+        writeln!(
+            f,
+            "<p>CPU Time: {:.3}s (user: {:.3}s, kernel: {:.3}s)</p>",
+            total_cpu_time.as_secs_f64(),
+            user_cpu_time.as_secs_f64(),
+            kernel_cpu_time.as_secs_f64()
+        )?;
+        if let Some(exit_code) = process_info.exit_code {
+            writeln!(f, "<p>Exit Code: {}</p>", exit_code)?;
+        }
+        if self.process_sample.get_capture_count() > 1 {
+            writeln!(
+                f,
+                "<p>Merged from {} captures</p>",
+                self.process_sample.get_capture_count()
+            )?;
+        }
+        writeln!(
+            f,
+            "<p><input id=\"search\" type=\"search\" placeholder=\"Search functions and modules\"></p>"
+        )
+    }
+
+    fn write_top_functions(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let total_samples: u32 = self
+            .process_sample
+            .get_threads()
+            .iter()
+            .map(ThreadSample::get_sample_count)
+            .sum();
+
+        writeln!(f, "<h2>Top Functions</h2>")?;
+        writeln!(
+            f,
+            "<table><tr><th>Function</th><th>Module</th><th>Self</th><th>Self %</th><th>Total</th><th>Total %</th></tr>"
+        )?;
+        for stats in top_functions(self.process_sample)
+            .iter()
+            .take(TOP_FUNCTIONS_LIMIT)
+        {
+            writeln!(
+                f,
+                "<tr class=\"frame\"><td>{}</td><td>{}</td><td class=\"count\">{}</td><td class=\"count\">{:.1}%</td><td class=\"count\">{}</td><td class=\"count\">{:.1}%</td></tr>",
+                escape_html(&stats.function),
+                escape_html(&stats.module),
+                stats.self_count,
+                percentage(stats.self_count, total_samples),
+                stats.total_count,
+                percentage(stats.total_count, total_samples)
+            )?;
+        }
+        writeln!(f, "</table>")
+    }
+
+    fn write_threads(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "<h2>Threads</h2>")?;
+        writeln!(f, "<div class=\"tree\">")?;
+        for thread in self.process_sample.get_threads() {
+            self.write_thread(f, thread)?;
+        }
+        writeln!(f, "</div>")
+    }
+
+    fn write_thread(&self, f: &mut std::fmt::Formatter, thread: &ThreadSample) -> std::fmt::Result {
+        let user_cpu_time = thread.get_user_cpu_time();
+        let kernel_cpu_time = thread.get_kernel_cpu_time();
+        let total_cpu_time = user_cpu_time + kernel_cpu_time;
+        let thread_samples = thread.get_sample_count();
+
+        writeln!(
+            f,
+            "<details class=\"thread\"><summary class=\"thread\">Thread {}    CPU Time: {:.3}s (user: {:.3}s, kernel: {:.3}s)    {} samples</summary>",
+            thread.get_thread_id(),
+            total_cpu_time.as_secs_f64(),
+            user_cpu_time.as_secs_f64(),
+            kernel_cpu_time.as_secs_f64(),
+            thread_samples
+        )?;
+
+        // Sample points with children become collapsible nodes, which are closed again
+        // once the DFS iteration returns to a shallower level.
+        let mut open_levels: Vec<u32> = Vec::new();
+        let mut sample_points = thread.sample_tree_dfs_iter().peekable();
+        while let Some(sample_point) = sample_points.next() {
+            while open_levels
+                .last()
+                .is_some_and(|&level| level >= sample_point.get_level())
+            {
+                open_levels.pop();
+                writeln!(f, "</details>")?;
+            }
+
+            let has_children = sample_points
+                .peek()
+                .is_some_and(|next| next.get_level() > sample_point.get_level());
+            let frame = self.frame_html(
+                sample_point.get_address(),
+                sample_point.get_count(),
+                thread_samples,
+            );
+            if has_children {
+                writeln!(f, "<details><summary class=\"frame\">{}</summary>", frame)?;
+                open_levels.push(sample_point.get_level());
+            } else {
+                writeln!(f, "<div class=\"frame\">{}</div>", frame)?;
+            }
+        }
+        for _ in open_levels {
+            writeln!(f, "</details>")?;
+        }
+
+        writeln!(f, "</details>")
+    }
+
+    fn frame_html(&self, address: u64, count: u32, thread_samples: u32) -> String {
+        let symbol = self.process_sample.get_symbol_table().symbol(address);
+        let function_name = symbol
+            .and_then(SymbolInfo::get_function)
+            .unwrap_or("{unknown}");
+        let module_name = symbol
+            .and_then(SymbolInfo::get_module_name)
+            .unwrap_or("{unknown}");
+
+        format!(
+            "{} ({:.1}%) - {} <span class=\"module\">(in {})</span> <span class=\"address\">[{:#x}]</span>",
+            count,
+            percentage(count, thread_samples),
+            escape_html(function_name),
+            escape_html(module_name),
+            address
+        )
+    }
+
+    fn write_modules(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "<h2>Modules</h2>")?;
+        writeln!(
+            f,
+            "<table><tr><th>Start</th><th>End</th><th>Name</th><th>Path</th><th>Host Path</th></tr>"
+        )?;
+        for module in &self.process_sample.process_info.modules {
+            writeln!(
+                f,
+                "<tr><td>{:#x}</td><td>{:#x}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                module.address_range().start,
+                module.address_range().end,
+                escape_html(module.name().unwrap_or("{unknown}")),
+                escape_html(module.file_path().unwrap_or("")),
+                escape_html(module.host_file_path().unwrap_or(""))
+            )?;
+        }
+        writeln!(f, "</table>")
+    }
+}
+
+impl std::fmt::Display for HtmlReport<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let process_info = &self.process_sample.process_info;
+        let title = format!(
+            "spinsample - {} ({})",
+            process_info
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default(),
+            process_info.pid
+        );
+
+        writeln!(f, "<!DOCTYPE html>")?;
+        writeln!(f, "<html><head><meta charset=\"utf-8\">")?;
+        writeln!(f, "<title>{}</title>", escape_html(&title))?;
+        writeln!(f, "<style>{}</style>", STYLE)?;
+        writeln!(f, "</head><body>")?;
+
+        self.write_summary(f)?;
+        self.write_top_functions(f)?;
+        self.write_threads(f)?;
+        self.write_modules(f)?;
+
+        writeln!(f, "<script>{}</script>", SCRIPT)?;
+        writeln!(f, "</body></html>")
+    }
+}
+
+/// Escape text for HTML or XML content and attribute values.
+pub(super) fn escape_html(text: &str) -> String {
+    text.chars()
+        .fold(String::with_capacity(text.len()), |mut escaped, c| {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&#39;"),
+                _ => escaped.push(c),
+            }
+            escaped
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html("std::vector<int>::operator[] & \"'"),
+            "std::vector&lt;int&gt;::operator[] &amp; &quot;&#39;"
+        );
+    }
+}
@@ -0,0 +1,544 @@
+//! Versioned JSON representation of a complete capture.
+//!
+//! The top level object has the following layout, where all durations are in nanoseconds
+//! and all addresses are absolute addresses in the sampled process:
+//!
+//! ```text
+//! {
+//!   "format": "spinsample",
+//!   "version": 2,
+//!   "interval_ns": 1000000,
+//!   "capture_count": 1,
+//!   "process": { "pid": 1234, "path": "C:\\...\\app.exe", "user_cpu_time_ns": 0, "kernel_cpu_time_ns": 0 },
+//!   "modules": [ { "path": "C:\\...\\app.exe", "base_address": 140695098146816, "size": 65536 } ],
+//!   "symbols": [ { "address": 140695098150000, "function": "main", "module": "app", "file": "C:\\...\\main.rs", "line": 12 } ],
+//!   "threads": [
+//!     {
+//!       "thread_id": 5678, "user_cpu_time_ns": 0, "kernel_cpu_time_ns": 0, "sample_count": 10,
+//!       "nodes": [ { "parent": null, "address": 140695098150000, "count": 10 } ]
+//!     }
+//!   ],
+//!   "stacks": [ { "parent": null, "address": 140695098150000 } ],
+//!   "timelines": [
+//!     {
+//!       "thread_id": 5678,
+//!       "samples": [
+//!         { "tick": 0, "timestamp_ns": 0, "user_cpu_time_ns": 0, "kernel_cpu_time_ns": 0, "stack": 0 }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! The `nodes` of a thread form its sample tree in depth-first pre-order, where `parent` is the
+//! index of the parent node within the same list, or `null` for the outermost frames.
+//! The `stacks` are shared by the samples of all timelines, where each stack is its innermost frame
+//! and the index of the stack of its caller within the same list, or `null` for the outermost frames.
+//! The `stack` of a timeline sample is the index of its stack, or `null` when its backtrace was empty.
+//! The `tick` of a timeline sample is the sequence number of the snapshot it was taken in, and its
+//! CPU times are the cumulative CPU times of the thread at that point; all three default to 0 when
+//! missing. Version 1 captures have no `stacks`, and store the `backtrace` of each timeline sample
+//! instead, ordered from the outermost frame to the innermost one.
+//! `function` and `module` of a symbol are `null` when the address couldn't be symbolicated.
+//! `file` and `line` of a symbol are the source location where its function starts, and are omitted
+//! when unknown.
+//! `parent_pid` of the process is only present when it was sampled as part of a process tree, and
+//! `exit_code` only when it exited during the capture.
+//! `host_path` of a module is the path of its image file as seen by the sampler, and is only present
+//! when the file isn't at the `path` the process reports, such as for processes in containers.
+//! `capture_count` is the number of captures merged into this one, and defaults to 1 when missing.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::Error;
+use crate::sampler::{
+    ModuleInfo, ProcessInfo, ProcessSample, StackId, StackTable, SymbolInfo, SymbolTable,
+    ThreadSample, ThreadTimeline, TimelineSample,
+};
+
+pub const FORMAT_NAME: &str = "spinsample";
+pub const FORMAT_VERSION: u32 = 2;
+/// The oldest version that can still be loaded.
+const MIN_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonCapture {
+    pub format: String,
+    pub version: u32,
+    pub interval_ns: u64,
+    #[serde(default = "default_capture_count")]
+    pub capture_count: u32,
+    pub process: JsonProcess,
+    pub modules: Vec<JsonModule>,
+    pub symbols: Vec<JsonSymbol>,
+    pub threads: Vec<JsonThread>,
+    #[serde(default)]
+    pub stacks: Vec<JsonStack>,
+    pub timelines: Vec<JsonTimeline>,
+}
+
+fn default_capture_count() -> u32 {
+    1
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonProcess {
+    pub pid: u32,
+    pub path: String,
+    pub user_cpu_time_ns: u64,
+    pub kernel_cpu_time_ns: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_pid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonModule {
+    pub path: String,
+    pub base_address: u64,
+    pub size: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonSymbol {
+    pub address: u64,
+    pub function: Option<String>,
+    pub module: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonThread {
+    pub thread_id: u32,
+    pub user_cpu_time_ns: u64,
+    pub kernel_cpu_time_ns: u64,
+    pub sample_count: u32,
+    pub nodes: Vec<JsonNode>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonNode {
+    pub parent: Option<usize>,
+    pub address: u64,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonStack {
+    pub parent: Option<usize>,
+    pub address: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonTimeline {
+    pub thread_id: u32,
+    pub samples: Vec<JsonTimelineSample>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonTimelineSample {
+    #[serde(default)]
+    pub tick: u64,
+    pub timestamp_ns: u64,
+    #[serde(default)]
+    pub user_cpu_time_ns: u64,
+    #[serde(default)]
+    pub kernel_cpu_time_ns: u64,
+    #[serde(default)]
+    pub stack: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backtrace: Option<Vec<u64>>,
+}
+
+impl From<&ProcessSample> for JsonCapture {
+    fn from(process_sample: &ProcessSample) -> Self {
+        let process_info = &process_sample.process_info;
+
+        let mut symbols: Vec<JsonSymbol> = process_sample
+            .get_symbol_table()
+            .iter()
+            .map(|(address, symbol)| JsonSymbol {
+                address,
+                function: symbol.get_function().map(str::to_string),
+                module: symbol
+                    .get_module()
+                    .map(|module| module.to_string_lossy().into_owned()),
+                file: symbol
+                    .get_file()
+                    .map(|file| file.to_string_lossy().into_owned()),
+                line: symbol.get_line(),
+            })
+            .collect();
+        symbols.sort_by_key(|symbol| symbol.address);
+
+        Self {
+            format: FORMAT_NAME.to_string(),
+            version: FORMAT_VERSION,
+            interval_ns: process_sample.get_interval().as_nanos() as u64,
+            capture_count: process_sample.get_capture_count(),
+            process: JsonProcess {
+                pid: process_info.pid,
+                path: process_info.path.to_string_lossy().into_owned(),
+                user_cpu_time_ns: process_info.user_cpu_time.as_nanos() as u64,
+                kernel_cpu_time_ns: process_info.kernel_cpu_time.as_nanos() as u64,
+                parent_pid: process_info.parent_pid,
+                exit_code: process_info.exit_code,
+            },
+            modules: process_info
+                .modules
+                .iter()
+                .map(|module| JsonModule {
+                    path: module.file_path().unwrap_or_default().to_string(),
+                    base_address: module.address_range().start,
+                    size: (module.address_range().end - module.address_range().start) as u32,
+                    host_path: module.host_file_path().map(str::to_string),
+                })
+                .collect(),
+            symbols,
+            threads: process_sample
+                .get_threads()
+                .iter()
+                .map(JsonThread::from)
+                .collect(),
+            stacks: process_sample
+                .get_stack_table()
+                .get_frames()
+                .iter()
+                .map(|frame| JsonStack {
+                    parent: frame.get_parent().map(|parent| parent as usize),
+                    address: frame.get_address(),
+                })
+                .collect(),
+            timelines: process_sample
+                .get_timelines()
+                .iter()
+                .map(JsonTimeline::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<&ThreadSample> for JsonThread {
+    fn from(thread: &ThreadSample) -> Self {
+        let mut nodes: Vec<JsonNode> = Vec::new();
+
+        // Levels and indices of the ancestors of the current sample point, outermost first.
+        let mut ancestors: Vec<(u32, usize)> = Vec::new();
+        for sample_point in thread.sample_tree_dfs_iter() {
+            while ancestors
+                .last()
+                .is_some_and(|&(level, _)| level >= sample_point.get_level())
+            {
+                ancestors.pop();
+            }
+
+            ancestors.push((sample_point.get_level(), nodes.len()));
+            nodes.push(JsonNode {
+                parent: ancestors.iter().rev().nth(1).map(|&(_, index)| index),
+                address: sample_point.get_address(),
+                count: sample_point.get_count(),
+            });
+        }
+
+        Self {
+            thread_id: thread.get_thread_id(),
+            user_cpu_time_ns: thread.get_user_cpu_time().as_nanos() as u64,
+            kernel_cpu_time_ns: thread.get_kernel_cpu_time().as_nanos() as u64,
+            sample_count: thread.get_sample_count(),
+            nodes,
+        }
+    }
+}
+
+impl From<&ThreadTimeline> for JsonTimeline {
+    fn from(timeline: &ThreadTimeline) -> Self {
+        Self {
+            thread_id: timeline.get_thread_id(),
+            samples: timeline
+                .get_samples()
+                .iter()
+                .map(|sample| JsonTimelineSample {
+                    tick: sample.get_tick(),
+                    timestamp_ns: sample.get_timestamp().as_nanos() as u64,
+                    user_cpu_time_ns: sample.get_user_cpu_time().as_nanos() as u64,
+                    kernel_cpu_time_ns: sample.get_kernel_cpu_time().as_nanos() as u64,
+                    stack: sample.get_stack_id().map(|stack_id| stack_id as usize),
+                    backtrace: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<JsonCapture> for ProcessSample {
+    type Error = Error;
+
+    fn try_from(capture: JsonCapture) -> Result<Self, Self::Error> {
+        if capture.format != FORMAT_NAME
+            || !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&capture.version)
+        {
+            return Err(Error::UnsupportedCapture(capture.format, capture.version));
+        }
+
+        let mut symbol_table = SymbolTable::new();
+        for symbol in capture.symbols {
+            symbol_table.insert(
+                symbol.address,
+                SymbolInfo::new(symbol.function, symbol.module.map(PathBuf::from))
+                    .with_source(symbol.file.map(PathBuf::from), symbol.line),
+            );
+        }
+
+        let threads = capture
+            .threads
+            .into_iter()
+            .map(ThreadSample::try_from)
+            .collect::<Result<_, _>>()?;
+
+        // Identical stacks in the file are merged, so map each stack index to its id.
+        let mut stack_table = StackTable::new();
+        let mut stack_ids: Vec<StackId> = Vec::with_capacity(capture.stacks.len());
+        for (index, stack) in capture.stacks.iter().enumerate() {
+            let parent = match stack.parent {
+                Some(parent) if parent >= index => {
+                    return Err(Error::InvalidCapture(format!(
+                        "stack {} has invalid parent {}",
+                        index, parent
+                    )));
+                }
+                parent => parent.map(|parent| stack_ids[parent]),
+            };
+            stack_ids.push(stack_table.intern_frame(parent, stack.address));
+        }
+
+        let mut timelines = Vec::new();
+        for json_timeline in capture.timelines {
+            let mut timeline = ThreadTimeline::new(json_timeline.thread_id);
+            for sample in json_timeline.samples {
+                let stack_id = match (sample.stack, sample.backtrace) {
+                    (Some(stack), _) => Some(*stack_ids.get(stack).ok_or_else(|| {
+                        Error::InvalidCapture(format!(
+                            "sample of thread {} has invalid stack {}",
+                            json_timeline.thread_id, stack
+                        ))
+                    })?),
+                    (None, Some(backtrace)) => stack_table.intern(backtrace),
+                    (None, None) => None,
+                };
+                timeline.add_sample(TimelineSample::new(
+                    sample.tick,
+                    Duration::from_nanos(sample.timestamp_ns),
+                    Duration::from_nanos(sample.user_cpu_time_ns),
+                    Duration::from_nanos(sample.kernel_cpu_time_ns),
+                    stack_id,
+                ));
+            }
+            timelines.push(timeline);
+        }
+
+        let mut process_info = ProcessInfo::new(
+            capture.process.pid,
+            PathBuf::from(capture.process.path),
+            capture
+                .modules
+                .into_iter()
+                .map(|module| {
+                    ModuleInfo::new(PathBuf::from(module.path), module.base_address, module.size)
+                        .with_host_path(module.host_path.map(PathBuf::from))
+                })
+                .collect(),
+            Duration::from_nanos(capture.process.user_cpu_time_ns),
+            Duration::from_nanos(capture.process.kernel_cpu_time_ns),
+        );
+        process_info.parent_pid = capture.process.parent_pid;
+        process_info.exit_code = capture.process.exit_code;
+
+        let mut process_sample = ProcessSample::new(
+            process_info,
+            threads,
+            timelines,
+            stack_table,
+            symbol_table,
+            Duration::from_nanos(capture.interval_ns),
+        );
+        process_sample.set_capture_count(capture.capture_count);
+        Ok(process_sample)
+    }
+}
+
+impl TryFrom<JsonThread> for ThreadSample {
+    type Error = Error;
+
+    /// Rebuild the sample tree by adding the backtrace of each node once for every sample
+    /// that ended in that node. Adding them in pre-order preserves the order of the children.
+    fn try_from(json_thread: JsonThread) -> Result<Self, Self::Error> {
+        let nodes = &json_thread.nodes;
+        let mut self_counts: Vec<u32> = nodes.iter().map(|node| node.count).collect();
+        for (index, node) in nodes.iter().enumerate() {
+            if let Some(parent) = node.parent {
+                if parent >= index {
+                    return Err(Error::InvalidCapture(format!(
+                        "node {} of thread {} has invalid parent {}",
+                        index, json_thread.thread_id, parent
+                    )));
+                }
+                self_counts[parent] = self_counts[parent].saturating_sub(node.count);
+            }
+        }
+
+        let mut thread_sample = ThreadSample::new(
+            json_thread.thread_id,
+            Duration::from_nanos(json_thread.user_cpu_time_ns),
+            Duration::from_nanos(json_thread.kernel_cpu_time_ns),
+        );
+        for (index, self_count) in self_counts.into_iter().enumerate() {
+            if self_count == 0 {
+                continue;
+            }
+            let mut backtrace = Vec::new();
+            let mut node = Some(index);
+            while let Some(current) = node {
+                backtrace.push(nodes[current].address);
+                node = nodes[current].parent;
+            }
+            thread_sample.add_backtrace_with_count(backtrace.iter().rev(), self_count);
+        }
+
+        // Backtraces without any frames only count towards the total.
+        let frame_samples: u32 = nodes
+            .iter()
+            .filter(|node| node.parent.is_none())
+            .map(|node| node.count)
+            .sum();
+        if json_thread.sample_count > frame_samples {
+            thread_sample.add_backtrace_with_count(
+                std::iter::empty(),
+                json_thread.sample_count - frame_samples,
+            );
+        }
+
+        Ok(thread_sample)
+    }
+}
+
+/// Load a capture previously saved in the JSON format.
+pub fn load_capture(path: &Path) -> Result<ProcessSample, Error> {
+    let file = std::fs::File::open(path).map_err(Error::ReadCaptureFailed)?;
+    let capture: JsonCapture = serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(Error::ParseCaptureFailed)?;
+    ProcessSample::try_from(capture)
+}
+
+/// JSON representation of a process sample, as described in the module documentation.
+pub struct JsonReport<'a> {
+    process_sample: &'a ProcessSample,
+}
+
+impl<'a> JsonReport<'a> {
+    pub fn new(process_sample: &'a ProcessSample) -> Self {
+        Self { process_sample }
+    }
+}
+
+impl std::fmt::Display for JsonReport<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let json = serde_json::to_string(&JsonCapture::from(self.process_sample))
+            .map_err(|_| std::fmt::Error)?;
+        write!(f, "{}", json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::test_samples::test_backtrace_sample;
+
+    #[test]
+    fn test_thread_nodes() {
+        let mut thread_sample = ThreadSample::new(1, Duration::default(), Duration::default());
+        thread_sample.add_backtrace([1, 2, 3].iter());
+        thread_sample.add_backtrace([1, 4].iter());
+        thread_sample.add_backtrace([5].iter());
+
+        let json_thread = JsonThread::from(&thread_sample);
+
+        assert_eq!(json_thread.sample_count, 3);
+        assert_eq!(
+            json_thread
+                .nodes
+                .iter()
+                .map(|node| (node.parent, node.address, node.count))
+                .collect::<Vec<_>>(),
+            vec![
+                (None, 1, 2),
+                (Some(0), 2, 1),
+                (Some(1), 3, 1),
+                (Some(0), 4, 1),
+                (None, 5, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thread_round_trip() {
+        let mut thread_sample = ThreadSample::new(1, Duration::from_millis(2), Duration::default());
+        thread_sample.add_backtrace([1, 2, 3].iter());
+        thread_sample.add_backtrace([1, 2].iter());
+        thread_sample.add_backtrace([1, 4].iter());
+        thread_sample.add_backtrace([].iter());
+
+        let json = serde_json::to_string(&JsonThread::from(&thread_sample)).unwrap();
+        let loaded_thread_sample =
+            ThreadSample::try_from(serde_json::from_str::<JsonThread>(&json).unwrap()).unwrap();
+
+        assert_eq!(loaded_thread_sample, thread_sample);
+    }
+
+    #[test]
+    fn test_version_1_backtraces_interned() {
+        let mut capture = JsonCapture::from(&test_backtrace_sample(&[], &[]));
+        capture.version = 1;
+        capture.timelines.push(JsonTimeline {
+            thread_id: 2,
+            samples: [vec![1, 2], vec![1, 2], vec![1, 3]]
+                .into_iter()
+                .map(|backtrace| JsonTimelineSample {
+                    tick: 0,
+                    timestamp_ns: 0,
+                    user_cpu_time_ns: 0,
+                    kernel_cpu_time_ns: 0,
+                    stack: None,
+                    backtrace: Some(backtrace),
+                })
+                .collect(),
+        });
+
+        let process_sample = ProcessSample::try_from(capture).unwrap();
+
+        let stack_table = process_sample.get_stack_table();
+        let samples = process_sample.get_timelines()[0].get_samples();
+        assert_eq!(stack_table.get_frames().len(), 3);
+        assert_eq!(samples[0].get_stack_id(), samples[1].get_stack_id());
+        assert_eq!(stack_table.backtrace(samples[2].get_stack_id()), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut capture = JsonCapture::from(&test_backtrace_sample(&[], &[]));
+        capture.version = FORMAT_VERSION + 1;
+
+        assert!(matches!(
+            ProcessSample::try_from(capture),
+            Err(Error::UnsupportedCapture(_, _))
+        ));
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::sampler::{
+    ModuleInfo, ProcessInfo, ProcessSample, StackTable, SymbolInfo, SymbolTable, ThreadSample,
+};
+
+/// Merge several captures, typically of different instances of the same program or of the same
+/// program at different times, into a single aggregate process sample.
+///
+/// Thread ids aren't meaningful across captures, so the sample trees of all threads are merged
+/// into one process-wide tree. Frames are matched by function and module name, since the same
+/// function generally has different addresses in different processes, so the merged sample uses
+/// synthetic addresses, one for each distinct frame. Unresolved frames are matched by module and
+/// offset and named `module+offset`; those outside of any module keep their own address and are
+/// only matched within their capture. The merged sample has no timelines.
+pub fn merge_captures(captures: &[ProcessSample]) -> Option<ProcessSample> {
+    let first = captures.first()?;
+
+    let mut symbol_table = SymbolTable::new();
+    let mut frame_addresses: HashMap<FrameKey, u64> = HashMap::new();
+    let mut thread_sample = ThreadSample::new(
+        0,
+        captures
+            .iter()
+            .flat_map(ProcessSample::get_threads)
+            .map(ThreadSample::get_user_cpu_time)
+            .sum(),
+        captures
+            .iter()
+            .flat_map(ProcessSample::get_threads)
+            .map(ThreadSample::get_kernel_cpu_time)
+            .sum(),
+    );
+
+    for (capture_index, capture) in captures.iter().enumerate() {
+        // Merged address of each address of the capture.
+        let mut merged_addresses: HashMap<u64, u64> = HashMap::new();
+        for thread in capture.get_threads() {
+            thread.visit_backtraces(|backtrace, count| {
+                let addresses: Vec<u64> = backtrace
+                    .iter()
+                    .map(|sample_point| {
+                        let address = sample_point.get_address();
+                        *merged_addresses.entry(address).or_insert_with(|| {
+                            let (key, symbol) = merged_frame(capture_index, capture, address);
+                            let next_address = frame_addresses.len() as u64 + 1;
+                            *frame_addresses.entry(key).or_insert_with(|| {
+                                symbol_table.insert(next_address, symbol);
+                                next_address
+                            })
+                        })
+                    })
+                    .collect();
+                thread_sample.add_backtrace_with_count(addresses.iter(), count);
+            });
+        }
+    }
+
+    let mut modules: Vec<ModuleInfo> = Vec::new();
+    for module in captures
+        .iter()
+        .flat_map(|capture| &capture.process_info.modules)
+    {
+        if !modules
+            .iter()
+            .any(|merged| merged.file_path() == module.file_path())
+        {
+            modules.push(module.clone());
+        }
+    }
+
+    let mut merged = ProcessSample::new(
+        ProcessInfo::new(
+            first.process_info.pid,
+            first.process_info.path.clone(),
+            modules,
+            captures
+                .iter()
+                .map(|capture| capture.process_info.user_cpu_time)
+                .sum::<Duration>(),
+            captures
+                .iter()
+                .map(|capture| capture.process_info.kernel_cpu_time)
+                .sum::<Duration>(),
+        ),
+        vec![thread_sample],
+        Vec::new(),
+        StackTable::new(),
+        symbol_table,
+        first.get_interval(),
+    );
+    merged.set_capture_count(captures.iter().map(ProcessSample::get_capture_count).sum());
+    Some(merged)
+}
+
+/// What frames of different captures are matched by.
+#[derive(PartialEq, Eq, Hash)]
+enum FrameKey {
+    /// A resolved function, by function and module name.
+    Function(String, Option<String>),
+    /// An unresolved address within a module, by module name and offset.
+    ModuleOffset(String, u64),
+    /// An unresolved address outside of any module, by capture index and address.
+    Address(usize, u64),
+}
+
+/// The key and merged symbol of an address of the specified capture.
+fn merged_frame(
+    capture_index: usize,
+    capture: &ProcessSample,
+    address: u64,
+) -> (FrameKey, SymbolInfo) {
+    let symbol = capture.get_symbol_table().symbol(address);
+    if let Some(symbol) = symbol.filter(|symbol| symbol.get_function().is_some()) {
+        let key = FrameKey::Function(
+            symbol.get_function().unwrap_or_default().to_string(),
+            symbol.get_module_name().map(str::to_string),
+        );
+        return (key, symbol.clone());
+    }
+
+    if let Some(module) = capture
+        .process_info
+        .modules
+        .iter()
+        .find(|module| module.address_range().contains(&address))
+    {
+        let module_name = module.name().unwrap_or("???").to_string();
+        let offset = address - module.address_range().start;
+        return (
+            FrameKey::ModuleOffset(module_name.clone(), offset),
+            SymbolInfo::new(
+                Some(format!("{}+{:#x}", module_name, offset)),
+                module.file_path().map(PathBuf::from),
+            ),
+        );
+    }
+
+    (
+        FrameKey::Address(capture_index, address),
+        SymbolInfo::new(
+            Some(format!("{:#x}", address)),
+            symbol.and_then(SymbolInfo::get_module).map(PathBuf::from),
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::test_samples::test_backtrace_sample;
+
+    #[test]
+    fn test_merge_captures() {
+        let first = test_backtrace_sample(
+            &[&[&[1, 2]], &[&[1, 3]]],
+            &[(1, "main"), (2, "a"), (3, "b")],
+        );
+        let second = test_backtrace_sample(&[&[&[10, 20], &[10, 20]]], &[(10, "main"), (20, "a")]);
+
+        let merged = merge_captures(&[first, second]).unwrap();
+
+        assert_eq!(merged.get_capture_count(), 2);
+        assert_eq!(merged.get_threads().len(), 1);
+
+        let symbol_table = merged.get_symbol_table();
+        let merged_tree: Vec<(u32, Option<&str>, u32)> = merged.get_threads()[0]
+            .sample_tree_dfs_iter()
+            .map(|sample_point| {
+                (
+                    sample_point.get_level(),
+                    symbol_table
+                        .symbol(sample_point.get_address())
+                        .and_then(SymbolInfo::get_function),
+                    sample_point.get_count(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            merged_tree,
+            vec![(1, Some("main"), 4), (2, Some("a"), 3), (2, Some("b"), 1)]
+        );
+    }
+
+    #[test]
+    fn test_merge_unresolved_frames() {
+        // The module is loaded at a different base in each capture.
+        let mut first = test_backtrace_sample(
+            &[&[&[1, 0x1010], &[1, 0x1020], &[1, 0x9000]]],
+            &[(1, "main")],
+        );
+        first.process_info.modules = vec![ModuleInfo::new(PathBuf::from("lib.dll"), 0x1000, 0x100)];
+        let mut second = test_backtrace_sample(&[&[&[10, 0x5010], &[10, 0x9000]]], &[(10, "main")]);
+        second.process_info.modules =
+            vec![ModuleInfo::new(PathBuf::from("lib.dll"), 0x5000, 0x100)];
+
+        let merged = merge_captures(&[first, second]).unwrap();
+
+        let symbol_table = merged.get_symbol_table();
+        let mut merged_tree: Vec<(u32, Option<&str>, u32)> = merged.get_threads()[0]
+            .sample_tree_dfs_iter()
+            .map(|sample_point| {
+                (
+                    sample_point.get_level(),
+                    symbol_table
+                        .symbol(sample_point.get_address())
+                        .and_then(SymbolInfo::get_function),
+                    sample_point.get_count(),
+                )
+            })
+            .collect();
+        merged_tree.sort();
+        assert_eq!(
+            merged_tree,
+            vec![
+                (1, Some("main"), 5),
+                (2, Some("0x9000"), 1),
+                (2, Some("0x9000"), 1),
+                (2, Some("lib.dll+0x10"), 2),
+                (2, Some("lib.dll+0x20"), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_no_captures() {
+        assert!(merge_captures(&[]).is_none());
+    }
+}
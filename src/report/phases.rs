@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::Duration;
+
+use super::top_functions::{function_key, percentage, top_functions_of_threads};
+use crate::sampler::{ProcessSample, StackTable, SymbolTable, ThreadTimeline, TimelineSample};
+
+/// Number of functions listed for each phase.
+const FUNCTIONS_PER_PHASE: usize = 3;
+
+/// Minimum number of samples per bucket, so buckets of short intervals aren't dominated by noise.
+const MIN_SAMPLES_PER_BUCKET: u32 = 10;
+
+/// Fraction of a bucket's innermost functions that must differ from the current phase
+/// for the bucket to start a new phase.
+const CHANGE_THRESHOLD: f64 = 0.5;
+
+/// A time range of a thread during which the distribution of its innermost functions is stable.
+#[derive(Debug, PartialEq)]
+pub struct Phase {
+    pub range: Range<Duration>,
+    pub sample_count: u32,
+}
+
+/// Samples within a bucket or phase, counted by the function of their innermost frame.
+struct Distribution {
+    range: Range<Duration>,
+    counts: HashMap<(String, String), u32>,
+    sample_count: u32,
+}
+
+impl Distribution {
+    fn new(start: Duration) -> Self {
+        Self {
+            range: start..start,
+            counts: HashMap::new(),
+            sample_count: 0,
+        }
+    }
+
+    fn add_sample(
+        &mut self,
+        sample: &TimelineSample,
+        stack_table: &StackTable,
+        symbol_table: &SymbolTable,
+    ) {
+        let key = match sample.get_stack_id() {
+            Some(stack_id) => {
+                function_key(symbol_table, stack_table.get_frame(stack_id).get_address())
+            }
+            None => Default::default(),
+        };
+        *self.counts.entry(key).or_default() += 1;
+        self.sample_count += 1;
+    }
+
+    fn merge(&mut self, other: Distribution) {
+        for (key, count) in other.counts {
+            *self.counts.entry(key).or_default() += count;
+        }
+        self.sample_count += other.sample_count;
+        self.range.end = other.range.end;
+    }
+
+    /// The total variation distance between both distributions, ranging from 0 when they
+    /// are identical to 1 when they have no functions in common.
+    fn distance(&self, other: &Distribution) -> f64 {
+        let fraction = |distribution: &Distribution, key| {
+            distribution.counts.get(key).copied().unwrap_or(0) as f64
+                / distribution.sample_count.max(1) as f64
+        };
+        let difference: f64 = self
+            .counts
+            .keys()
+            .chain(
+                other
+                    .counts
+                    .keys()
+                    .filter(|key| !self.counts.contains_key(*key)),
+            )
+            .map(|key| (fraction(self, key) - fraction(other, key)).abs())
+            .sum();
+        difference / 2.0
+    }
+
+    fn into_phase(self) -> Phase {
+        Phase {
+            range: self.range,
+            sample_count: self.sample_count,
+        }
+    }
+}
+
+/// Split the timeline of a thread into phases where its dominant innermost functions change.
+///
+/// The samples are grouped into buckets of at least the specified length, and a bucket starts a new
+/// phase when it differs significantly from the current phase and the following bucket confirms the
+/// change, so that a single unusual bucket doesn't split a phase.
+pub fn detect_phases(
+    timeline: &ThreadTimeline,
+    stack_table: &StackTable,
+    symbol_table: &SymbolTable,
+    bucket_length: Duration,
+) -> Vec<Phase> {
+    let mut buckets: Vec<Distribution> = Vec::new();
+    for sample in timeline.get_samples() {
+        let current = buckets.last_mut().filter(|bucket| {
+            bucket.sample_count < MIN_SAMPLES_PER_BUCKET
+                || sample.get_timestamp() < bucket.range.start + bucket_length
+        });
+        let bucket = match current {
+            Some(bucket) => bucket,
+            None => {
+                buckets.push(Distribution::new(sample.get_timestamp()));
+                buckets.last_mut().unwrap()
+            }
+        };
+        bucket.add_sample(sample, stack_table, symbol_table);
+        bucket.range.end = sample.get_timestamp();
+    }
+    // Each bucket ends where the next one starts.
+    for index in 1..buckets.len() {
+        buckets[index - 1].range.end = buckets[index].range.start;
+    }
+
+    let mut phases: Vec<Distribution> = Vec::new();
+    let mut candidate: Option<Distribution> = None;
+    for bucket in buckets {
+        let Some(phase) = phases.last_mut() else {
+            phases.push(bucket);
+            continue;
+        };
+
+        if let Some(pending) = candidate.take() {
+            if phase.distance(&bucket) > CHANGE_THRESHOLD
+                && pending.distance(&bucket) <= CHANGE_THRESHOLD
+            {
+                let mut new_phase = pending;
+                new_phase.merge(bucket);
+                phases.push(new_phase);
+            } else {
+                phase.merge(pending);
+                if phase.distance(&bucket) > CHANGE_THRESHOLD {
+                    candidate = Some(bucket);
+                } else {
+                    phase.merge(bucket);
+                }
+            }
+        } else if phase.distance(&bucket) > CHANGE_THRESHOLD {
+            candidate = Some(bucket);
+        } else {
+            phase.merge(bucket);
+        }
+    }
+    if let (Some(phase), Some(pending)) = (phases.last_mut(), candidate) {
+        phase.merge(pending);
+    }
+
+    phases.into_iter().map(Distribution::into_phase).collect()
+}
+
+/// Text report of the phases of each thread, along with the top functions of each phase.
+pub struct PhaseReport<'a> {
+    process_sample: &'a ProcessSample,
+    bucket_length: Duration,
+}
+
+impl<'a> PhaseReport<'a> {
+    pub fn new(process_sample: &'a ProcessSample) -> Self {
+        // Buckets of 1/50th of the capture keep the cost of the analysis bounded on long captures.
+        let bucket_length = (process_sample.get_duration() / 50).max(Duration::from_millis(100));
+        Self {
+            process_sample,
+            bucket_length,
+        }
+    }
+}
+
+impl std::fmt::Display for PhaseReport<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Process: {} - {}",
+            self.process_sample.process_info.pid,
+            self.process_sample.process_info.path.to_string_lossy()
+        )?;
+
+        let stack_table = self.process_sample.get_stack_table();
+        let symbol_table = self.process_sample.get_symbol_table();
+        for timeline in self.process_sample.get_timelines() {
+            let phases = detect_phases(timeline, stack_table, symbol_table, self.bucket_length);
+
+            writeln!(f)?;
+            writeln!(
+                f,
+                "Thread {}    Phases: {}",
+                timeline.get_thread_id(),
+                phases.len()
+            )?;
+            for (index, phase) in phases.iter().enumerate() {
+                // The last phase lasts until the end of its last sample.
+                let end = if index + 1 == phases.len() {
+                    phase.range.end + self.process_sample.get_interval()
+                } else {
+                    phase.range.end
+                };
+                writeln!(
+                    f,
+                    "  Phase {}: {:.3}s - {:.3}s    Samples: {}",
+                    index + 1,
+                    phase.range.start.as_secs_f64(),
+                    end.as_secs_f64(),
+                    phase.sample_count
+                )?;
+
+                let thread_sample = timeline.thread_sample(&(phase.range.start..end), stack_table);
+                for stats in top_functions_of_threads(&[thread_sample], symbol_table)
+                    .iter()
+                    .take(FUNCTIONS_PER_PHASE)
+                {
+                    writeln!(
+                        f,
+                        "    {:>6} {:>6.1}%  {}  (in {})",
+                        stats.self_count,
+                        percentage(stats.self_count, phase.sample_count),
+                        stats.function,
+                        stats.module
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeline(leaf_addresses: &[u64], stack_table: &mut StackTable) -> ThreadTimeline {
+        let mut timeline = ThreadTimeline::new(1);
+        for (index, &address) in leaf_addresses.iter().enumerate() {
+            timeline.add_sample(TimelineSample::new(
+                index as u64,
+                Duration::from_millis(index as u64),
+                Duration::default(),
+                Duration::default(),
+                stack_table.intern([1, address]),
+            ));
+        }
+        timeline
+    }
+
+    #[test]
+    fn test_phase_change_detected() {
+        let leaf_addresses: Vec<u64> = [[2; 40], [3; 40]].concat();
+
+        let mut stack_table = StackTable::new();
+        let phases = detect_phases(
+            &timeline(&leaf_addresses, &mut stack_table),
+            &stack_table,
+            &SymbolTable::new(),
+            Duration::from_millis(10),
+        );
+
+        assert_eq!(
+            phases,
+            vec![
+                Phase {
+                    range: Duration::ZERO..Duration::from_millis(40),
+                    sample_count: 40
+                },
+                Phase {
+                    range: Duration::from_millis(40)..Duration::from_millis(79),
+                    sample_count: 40
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_single_unusual_bucket_ignored() {
+        let leaf_addresses: Vec<u64> = [&[2; 30][..], &[3; 10], &[2; 30]].concat();
+
+        let mut stack_table = StackTable::new();
+        let phases = detect_phases(
+            &timeline(&leaf_addresses, &mut stack_table),
+            &stack_table,
+            &SymbolTable::new(),
+            Duration::from_millis(10),
+        );
+
+        assert_eq!(
+            phases,
+            vec![Phase {
+                range: Duration::ZERO..Duration::from_millis(69),
+                sample_count: 70
+            }]
+        );
+    }
+}
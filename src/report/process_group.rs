@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use crate::sampler::{ProcessSample, ThreadSample};
+
+/// Text report of several processes sampled together, such as a process tree, with a combined
+/// summary of all processes followed by the sample analysis of each one.
+pub struct ProcessGroupReport<'a> {
+    process_samples: &'a [ProcessSample],
+}
+
+impl<'a> ProcessGroupReport<'a> {
+    pub fn new(process_samples: &'a [ProcessSample]) -> Self {
+        Self { process_samples }
+    }
+}
+
+fn sample_count(process_sample: &ProcessSample) -> u32 {
+    process_sample
+        .get_threads()
+        .iter()
+        .map(ThreadSample::get_sample_count)
+        .sum()
+}
+
+impl std::fmt::Display for ProcessGroupReport<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let user_cpu_time: Duration = self
+            .process_samples
+            .iter()
+            .map(|process_sample| process_sample.process_info.user_cpu_time)
+            .sum();
+        let kernel_cpu_time: Duration = self
+            .process_samples
+            .iter()
+            .map(|process_sample| process_sample.process_info.kernel_cpu_time)
+            .sum();
+        let total_cpu_time = user_cpu_time + kernel_cpu_time;
+        writeln!(
+            f,
+            "Processes: {}    Samples: {}    CPU Time: {:.3}s (user: {:.3}s, kernel: {:.3}s)",
+            self.process_samples.len(),
+            self.process_samples.iter().map(sample_count).sum::<u32>(),
+            total_cpu_time.as_secs_f64(),
+            user_cpu_time.as_secs_f64(),
+            kernel_cpu_time.as_secs_f64()
+        )?;
+
+        writeln!(
+            f,
+            "  {:>8} {:>8} {:>8} {:>10} {:>10}  Path",
+            "Pid", "Parent", "Samples", "CPU Time", "Exit Code"
+        )?;
+        for process_sample in self.process_samples {
+            let process_info = &process_sample.process_info;
+            writeln!(
+                f,
+                "  {:>8} {:>8} {:>8} {:>9.3}s {:>10}  {}",
+                process_info.pid,
+                process_info
+                    .parent_pid
+                    .map_or("-".to_string(), |pid| pid.to_string()),
+                sample_count(process_sample),
+                (process_info.user_cpu_time + process_info.kernel_cpu_time).as_secs_f64(),
+                process_info
+                    .exit_code
+                    .map_or("-".to_string(), |exit_code| exit_code.to_string()),
+                process_info.path.to_string_lossy()
+            )?;
+        }
+
+        for process_sample in self.process_samples {
+            writeln!(f)?;
+            write!(f, "{}", process_sample)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::report::test_samples::test_backtrace_sample;
+
+    #[test]
+    fn test_summary_lists_each_process() {
+        let process_samples =
+            [(1, None, 250), (2, Some(1), 500)].map(|(pid, parent_pid, cpu_time)| {
+                let mut process_sample = test_backtrace_sample(&[], &[]);
+                process_sample.process_info.pid = pid;
+                process_sample.process_info.path = PathBuf::from(format!("app{}.exe", pid));
+                process_sample.process_info.user_cpu_time = Duration::from_millis(cpu_time);
+                process_sample.process_info.parent_pid = parent_pid;
+                process_sample
+            });
+
+        let report = ProcessGroupReport::new(&process_samples).to_string();
+
+        assert!(report.starts_with("Processes: 2    Samples: 0    CPU Time: 0.750s"));
+        assert!(report.contains("         2        1        0     0.500s          -  app2.exe\n"));
+        assert!(report.contains("Process: 1 - app1.exe"));
+        assert!(report.contains("Process: 2 - app2.exe"));
+    }
+}
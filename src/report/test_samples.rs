@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::sampler::{
+    ProcessInfo, ProcessSample, StackTable, SymbolInfo, SymbolTable, ThreadSample, ThreadTimeline,
+    TimelineSample,
+};
+
+/// Process sample of pid 1 with the specified contents and a 1 ms interval.
+pub fn test_process_sample(
+    threads: Vec<ThreadSample>,
+    timelines: Vec<ThreadTimeline>,
+    stack_table: StackTable,
+    symbol_table: SymbolTable,
+) -> ProcessSample {
+    ProcessSample::new(
+        ProcessInfo::new(
+            1,
+            PathBuf::new(),
+            Vec::new(),
+            Duration::default(),
+            Duration::default(),
+        ),
+        threads,
+        timelines,
+        stack_table,
+        symbol_table,
+        Duration::from_millis(1),
+    )
+}
+
+/// Process sample with a thread for each list of backtraces, numbered from 1, holding both the
+/// sample tree and a timeline with one sample per millisecond. The symbols name functions of an
+/// `app` module.
+pub fn test_backtrace_sample(
+    thread_backtraces: &[&[&[u64]]],
+    symbols: &[(u64, &str)],
+) -> ProcessSample {
+    let mut stack_table = StackTable::new();
+    let (threads, timelines) = thread_backtraces
+        .iter()
+        .zip(1..)
+        .map(|(backtraces, thread_id)| {
+            let mut thread_sample =
+                ThreadSample::new(thread_id, Duration::default(), Duration::default());
+            let mut timeline = ThreadTimeline::new(thread_id);
+            for (tick, backtrace) in backtraces.iter().enumerate() {
+                thread_sample.add_backtrace(backtrace.iter());
+                timeline.add_sample(TimelineSample::new(
+                    tick as u64,
+                    Duration::from_millis(tick as u64),
+                    Duration::default(),
+                    Duration::default(),
+                    stack_table.intern(backtrace.iter().copied()),
+                ));
+            }
+            (thread_sample, timeline)
+        })
+        .unzip();
+
+    let mut symbol_table = SymbolTable::new();
+    for (address, function) in symbols {
+        symbol_table.insert(
+            *address,
+            SymbolInfo::new(Some(function.to_string()), Some(PathBuf::from("app"))),
+        );
+    }
+
+    test_process_sample(threads, timelines, stack_table, symbol_table)
+}
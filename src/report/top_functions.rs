@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use crate::sampler::{ProcessSample, SymbolInfo, SymbolTable, ThreadSample};
+
+/// Sample counts of a single function, aggregated across all threads of a process sample.
+#[derive(Debug, PartialEq)]
+pub struct FunctionStats {
+    pub function: String,
+    pub module: String,
+    /// Number of samples where the function was the innermost frame.
+    pub self_count: u32,
+    /// Number of samples where the function was anywhere on the stack.
+    pub total_count: u32,
+}
+
+/// Aggregate the sample trees of all threads by function, ordered by descending self count.
+pub fn top_functions(process_sample: &ProcessSample) -> Vec<FunctionStats> {
+    top_functions_of_threads(
+        process_sample.get_threads(),
+        process_sample.get_symbol_table(),
+    )
+}
+
+/// Same as `top_functions`, but for the specified sample trees, such as those of a time window.
+pub fn top_functions_of_threads(
+    threads: &[ThreadSample],
+    symbol_table: &SymbolTable,
+) -> Vec<FunctionStats> {
+    let mut function_stats: HashMap<(String, String), FunctionStats> = HashMap::new();
+
+    for thread in threads {
+        let sample_points: Vec<_> = thread.sample_tree_dfs_iter().collect();
+        let mut self_counts: Vec<u32> = sample_points.iter().map(|p| p.get_count()).collect();
+        let keys: Vec<_> = sample_points
+            .iter()
+            .map(|p| function_key(symbol_table, p.get_address()))
+            .collect();
+
+        // Indices of the ancestors of the current sample point, outermost first.
+        let mut ancestors: Vec<usize> = Vec::new();
+        for (index, sample_point) in sample_points.iter().enumerate() {
+            while ancestors.last().is_some_and(|&ancestor| {
+                sample_points[ancestor].get_level() >= sample_point.get_level()
+            }) {
+                ancestors.pop();
+            }
+            if let Some(&parent) = ancestors.last() {
+                self_counts[parent] -= sample_point.get_count();
+            }
+
+            // Only count recursive calls once towards the total count.
+            let is_recursive = ancestors
+                .iter()
+                .any(|&ancestor| keys[ancestor] == keys[index]);
+            let stats = function_stats
+                .entry(keys[index].clone())
+                .or_insert_with(|| FunctionStats {
+                    function: keys[index].0.clone(),
+                    module: keys[index].1.clone(),
+                    self_count: 0,
+                    total_count: 0,
+                });
+            if !is_recursive {
+                stats.total_count += sample_point.get_count();
+            }
+
+            ancestors.push(index);
+        }
+
+        for (key, self_count) in keys.into_iter().zip(self_counts) {
+            if let Some(stats) = function_stats.get_mut(&key) {
+                stats.self_count += self_count;
+            }
+        }
+    }
+
+    let mut top_functions: Vec<FunctionStats> = function_stats.into_values().collect();
+    top_functions.sort_by(|a, b| {
+        b.self_count
+            .cmp(&a.self_count)
+            .then(b.total_count.cmp(&a.total_count))
+            .then(a.function.cmp(&b.function))
+    });
+    top_functions
+}
+
+/// Functions are identified by name and module, since samples within the same function
+/// generally have different addresses. Unknown functions fall back to the sampled address.
+pub(super) fn function_key(symbol_table: &SymbolTable, address: u64) -> (String, String) {
+    let symbol = symbol_table.symbol(address);
+    let function = symbol
+        .and_then(SymbolInfo::get_function)
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:#x}", address));
+    let module = symbol
+        .and_then(SymbolInfo::get_module_name)
+        .unwrap_or("{unknown}")
+        .to_string();
+
+    (function, module)
+}
+
+/// Share of the count in the total, in percent, or zero when there is nothing to share.
+pub(super) fn percentage(count: u32, total: u32) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::test_samples::test_backtrace_sample;
+
+    fn counts(top_functions: &[FunctionStats]) -> Vec<(&str, u32, u32)> {
+        top_functions
+            .iter()
+            .map(|stats| (stats.function.as_str(), stats.self_count, stats.total_count))
+            .collect()
+    }
+
+    #[test]
+    fn test_self_and_total_counts() {
+        let process_sample = test_backtrace_sample(&[&[&[1, 2], &[1, 2], &[1, 3], &[1]]], &[]);
+
+        assert_eq!(
+            counts(&top_functions(&process_sample)),
+            vec![("0x2", 2, 2), ("0x1", 1, 4), ("0x3", 1, 1)]
+        );
+    }
+
+    #[test]
+    fn test_recursion_counted_once() {
+        let process_sample = test_backtrace_sample(&[&[&[1, 2, 1, 2]]], &[]);
+
+        assert_eq!(
+            counts(&top_functions(&process_sample)),
+            vec![("0x2", 1, 1), ("0x1", 0, 1)]
+        );
+    }
+}
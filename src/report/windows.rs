@@ -0,0 +1,117 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use super::top_functions::{percentage, top_functions_of_threads};
+use crate::sampler::{ProcessSample, ThreadSample};
+
+/// Number of functions listed for each window.
+const FUNCTIONS_PER_WINDOW: usize = 5;
+
+/// Text summary of the top functions within each of a number of equal time windows of a capture,
+/// which shows changes in behavior that are diluted in the aggregate of the whole capture.
+pub struct WindowSummary<'a> {
+    process_sample: &'a ProcessSample,
+    range: Range<Duration>,
+    window_count: u32,
+}
+
+impl<'a> WindowSummary<'a> {
+    /// Split the specified time range of the capture into `window_count` windows.
+    pub fn new(
+        process_sample: &'a ProcessSample,
+        range: Range<Duration>,
+        window_count: u32,
+    ) -> Self {
+        Self {
+            process_sample,
+            range,
+            window_count: window_count.max(1),
+        }
+    }
+
+    fn windows(&self) -> impl Iterator<Item = Range<Duration>> + '_ {
+        let window_length = self.range.end.saturating_sub(self.range.start) / self.window_count;
+        (0..self.window_count).map(move |index| {
+            let start = self.range.start + window_length * index;
+            if index + 1 == self.window_count {
+                start..self.range.end
+            } else {
+                start..start + window_length
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for WindowSummary<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Process: {} - {}",
+            self.process_sample.process_info.pid,
+            self.process_sample.process_info.path.to_string_lossy()
+        )?;
+
+        for (index, window) in self.windows().enumerate() {
+            let threads = self.process_sample.window_threads(&window);
+            let sample_count: u32 = threads.iter().map(ThreadSample::get_sample_count).sum();
+            let cpu_time: Duration = threads
+                .iter()
+                .map(|thread| thread.get_user_cpu_time() + thread.get_kernel_cpu_time())
+                .sum();
+
+            writeln!(f)?;
+            writeln!(
+                f,
+                "Window {}: {:.3}s - {:.3}s    Samples: {}    CPU Time: {:.3}s",
+                index + 1,
+                window.start.as_secs_f64(),
+                window.end.as_secs_f64(),
+                sample_count,
+                cpu_time.as_secs_f64()
+            )?;
+
+            let top_functions =
+                top_functions_of_threads(&threads, self.process_sample.get_symbol_table());
+            for stats in top_functions.iter().take(FUNCTIONS_PER_WINDOW) {
+                writeln!(
+                    f,
+                    "  {:>6} {:>6.1}%  {}  (in {})",
+                    stats.self_count,
+                    percentage(stats.self_count, sample_count),
+                    stats.function,
+                    stats.module
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::test_samples::test_backtrace_sample;
+
+    #[test]
+    fn test_windows_cover_capture() {
+        let process_sample = test_backtrace_sample(&[&[&[1], &[1], &[1], &[1]]], &[]);
+
+        let window_summary = WindowSummary::new(
+            &process_sample,
+            Duration::ZERO..process_sample.get_duration(),
+            2,
+        );
+
+        assert_eq!(
+            window_summary.windows().collect::<Vec<_>>(),
+            vec![
+                Duration::ZERO..Duration::from_millis(2),
+                Duration::from_millis(2)..Duration::from_millis(4)
+            ]
+        );
+        assert!(window_summary
+            .to_string()
+            .contains("Window 2: 0.002s - 0.004s    Samples: 2    CPU Time: 0.000s"));
+    }
+}
@@ -0,0 +1,158 @@
+use std::ffi::c_void;
+use std::path::Path;
+
+use windows::core::{PCWSTR, PWSTR};
+use windows::Win32::Foundation::*;
+use windows::Win32::NetworkManagement::IpHelper::*;
+use windows::Win32::System::RestartManager::*;
+use windows_strings::HSTRING;
+
+use super::*;
+
+// Address families of the IP helper tables, from ws2def.h.
+const AF_INET: u32 = 2;
+const AF_INET6: u32 = 23;
+
+/// Find the processes listening on the TCP port or bound to the UDP port, in ascending pid order.
+pub fn pids_for_port(port: u16) -> windows::core::Result<Vec<Pid>> {
+    let mut pids = Vec::new();
+    unsafe {
+        let tcp_rows: Vec<MIB_TCPROW_OWNER_PID> = table_rows(|table, size| {
+            GetExtendedTcpTable(table, size, false, AF_INET, TCP_TABLE_OWNER_PID_LISTENER, 0)
+        })?;
+        pids.extend(
+            tcp_rows
+                .iter()
+                .filter(|row| local_port(row.dwLocalPort) == port)
+                .map(|row| row.dwOwningPid),
+        );
+
+        let tcp6_rows: Vec<MIB_TCP6ROW_OWNER_PID> = table_rows(|table, size| {
+            GetExtendedTcpTable(
+                table,
+                size,
+                false,
+                AF_INET6,
+                TCP_TABLE_OWNER_PID_LISTENER,
+                0,
+            )
+        })?;
+        pids.extend(
+            tcp6_rows
+                .iter()
+                .filter(|row| local_port(row.dwLocalPort) == port)
+                .map(|row| row.dwOwningPid),
+        );
+
+        let udp_rows: Vec<MIB_UDPROW_OWNER_PID> = table_rows(|table, size| {
+            GetExtendedUdpTable(table, size, false, AF_INET, UDP_TABLE_OWNER_PID, 0)
+        })?;
+        pids.extend(
+            udp_rows
+                .iter()
+                .filter(|row| local_port(row.dwLocalPort) == port)
+                .map(|row| row.dwOwningPid),
+        );
+
+        let udp6_rows: Vec<MIB_UDP6ROW_OWNER_PID> = table_rows(|table, size| {
+            GetExtendedUdpTable(table, size, false, AF_INET6, UDP_TABLE_OWNER_PID, 0)
+        })?;
+        pids.extend(
+            udp6_rows
+                .iter()
+                .filter(|row| local_port(row.dwLocalPort) == port)
+                .map(|row| row.dwOwningPid),
+        );
+    }
+    pids.sort_unstable();
+    pids.dedup();
+    Ok(pids)
+}
+
+/// Find the processes that have the file open, in ascending pid order, using the Restart Manager.
+pub fn pids_for_file(file: &Path) -> windows::core::Result<Vec<Pid>> {
+    let file = HSTRING::from(std::path::absolute(file)?.as_path());
+
+    let mut session = 0;
+    let mut session_key = [0u16; CCH_RM_SESSION_KEY as usize + 1];
+    unsafe { RmStartSession(&mut session, None, PWSTR(session_key.as_mut_ptr())).ok()? };
+
+    let processes = unsafe { session_processes(session, PCWSTR(file.as_ptr())) };
+    unsafe { _ = RmEndSession(session) };
+
+    let mut pids: Vec<Pid> = processes?
+        .iter()
+        .map(|process| process.Process.dwProcessId)
+        .collect();
+    pids.sort_unstable();
+    pids.dedup();
+    Ok(pids)
+}
+
+unsafe fn session_processes(
+    session: u32,
+    file: PCWSTR,
+) -> windows::core::Result<Vec<RM_PROCESS_INFO>> {
+    RmRegisterResources(session, Some(&[file]), None, None).ok()?;
+
+    // The list can grow between the calls, so keep asking until it fits.
+    let mut processes = Vec::new();
+    loop {
+        let mut needed_count = 0;
+        let mut count = processes.len() as u32;
+        let mut reboot_reasons = 0;
+        let error = RmGetList(
+            session,
+            &mut needed_count,
+            &mut count,
+            Some(processes.as_mut_ptr()),
+            &mut reboot_reasons,
+        );
+        if error == ERROR_MORE_DATA {
+            processes.resize(needed_count as usize, RM_PROCESS_INFO::default());
+            continue;
+        }
+        error.ok()?;
+        processes.truncate(count as usize);
+        return Ok(processes);
+    }
+}
+
+/// Query one of the IP helper tables, which all start with the number of rows followed by the rows.
+unsafe fn table_rows<Row: Clone>(
+    query: impl Fn(Option<*mut c_void>, *mut u32) -> u32,
+) -> windows::core::Result<Vec<Row>> {
+    let mut size = 0;
+    let mut buffer: Vec<u32> = Vec::new();
+    loop {
+        let error = WIN32_ERROR(query(Some(buffer.as_mut_ptr() as *mut c_void), &mut size));
+        if error == ERROR_INSUFFICIENT_BUFFER {
+            buffer.resize((size as usize).div_ceil(size_of::<u32>()), 0);
+            continue;
+        }
+        error.ok()?;
+        break;
+    }
+
+    let Some(&row_count) = buffer.first() else {
+        return Ok(Vec::new());
+    };
+    let rows = buffer[1..].as_ptr() as *const Row;
+    Ok(std::slice::from_raw_parts(rows, row_count as usize).to_vec())
+}
+
+/// The port of a table row, which holds it in network byte order in its lower 16 bits.
+fn local_port(table_port: u32) -> u16 {
+    u16::from_be(table_port as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_port_in_network_byte_order() {
+        assert_eq!(local_port(0x0000_901f), 8080);
+        assert_eq!(local_port(0x0000_5000), 80);
+    }
+}
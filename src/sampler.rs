@@ -1,6 +1,7 @@
-use std::collections::HashSet;
 use std::ffi::*;
+use std::os::windows::process::CommandExt;
 use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, PoisonError};
 use std::time::Duration;
 
 use windows::core::{Owned, PCWSTR, PWSTR};
@@ -11,42 +12,95 @@ use windows::Win32::System::Threading::*;
 use super::cancel_status::*;
 use super::*;
 
+mod aggregator;
 mod backtrace;
 mod error;
+mod function_tables;
+mod jit_symbol_map;
+mod mapped_image;
 mod module_info;
+mod process_capture;
 mod process_info;
 mod process_sample;
+mod process_tree;
 mod raw_sample;
 mod sample_point;
+mod stack_table;
+mod symbol_cache;
 mod symbol_table;
+mod symbolication_worker;
 mod symbolicator;
 mod thread_sample;
+mod timeline;
 
 pub use error::Error;
 pub use module_info::ModuleInfo;
 pub use process_info::ProcessInfo;
 pub use process_sample::ProcessSample;
 pub use sample_point::SamplePoint;
+pub use stack_table::{StackFrame, StackId, StackTable};
 pub use symbol_table::{SymbolInfo, SymbolTable};
 pub use symbolicator::Symbolicator;
 pub use thread_sample::ThreadSample;
+pub use timeline::{ThreadTimeline, TimelineSample};
 
+use aggregator::SampleAggregator;
 use backtrace::Backtrace;
+use jit_symbol_map::JitSymbolMap;
+use mapped_image::DosDevices;
+use process_capture::ProcessCapture;
+use process_tree::ProcessTreeWatcher;
 use raw_sample::RawSample;
+use symbol_cache::{ModuleIdentity, SymbolCache};
+use symbolication_worker::SymbolicationWorker;
 
 pub type Pid = u32;
 pub type Tid = u32;
 
+/// DbgHelp functions are single threaded, so the calls of the sampling and symbolication threads
+/// must be serialized, stack walks included.
+fn dbghelp_lock() -> MutexGuard<'static, ()> {
+    static DBGHELP_LOCK: Mutex<()> = Mutex::new(());
+    DBGHELP_LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Read a value from the memory of the sampled process.
+unsafe fn read_process_memory<T: Default>(process_handle: HANDLE, address: u64) -> Option<T> {
+    let mut value = T::default();
+    ReadProcessMemory(
+        process_handle,
+        address as *const c_void,
+        &raw mut value as *mut c_void,
+        size_of::<T>(),
+        None,
+    )
+    .ok()?;
+    Some(value)
+}
+
 struct Sampler {
     process_handle: Owned<HANDLE>,
 }
 
+// The process handle can be used from any thread, so captures can be prepared off the sampling
+// thread.
+unsafe impl Send for Sampler {}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        function_tables::forget_process(*self.process_handle);
+    }
+}
+
 impl Sampler {
     fn attach(pid: Pid) -> Result<Sampler, Error> {
         let process_handle = unsafe {
             Owned::new(
                 OpenProcess(
-                    PROCESS_VM_READ | PROCESS_SUSPEND_RESUME | PROCESS_QUERY_INFORMATION,
+                    PROCESS_VM_READ
+                        | PROCESS_SUSPEND_RESUME
+                        | PROCESS_QUERY_INFORMATION
+                        | PROCESS_SYNCHRONIZE,
                     false,
                     pid,
                 )
@@ -77,6 +131,27 @@ impl Sampler {
         }
     }
 
+    /// The exit code of the process, once it has exited.
+    fn exit_code(&self) -> Option<u32> {
+        unsafe {
+            if WaitForSingleObject(*self.process_handle, 0) != WAIT_OBJECT_0 {
+                return None;
+            }
+            let mut exit_code = 0;
+            GetExitCodeProcess(*self.process_handle, &mut exit_code).ok()?;
+            Some(exit_code)
+        }
+    }
+
+    /// Resume the threads of a process that was created suspended.
+    fn resume_threads(&self) {
+        unsafe {
+            for thread_handle in self.thread_iter() {
+                ResumeThread(thread_handle);
+            }
+        }
+    }
+
     fn process_cpu_time(&self) -> (std::time::Duration, std::time::Duration) {
         let mut creation_time = FILETIME::default();
         let mut exit_time = FILETIME::default();
@@ -156,20 +231,27 @@ impl Sampler {
                 Some(callback),
                 Some(modules_ptr as *mut c_void),
             );
-            *Box::from_raw(modules_ptr)
+            mapped_image::add_host_paths(*self.process_handle, *Box::from_raw(modules_ptr))
         }
     }
 
-    unsafe fn snapshot_threads(&self) -> Result<Vec<RawSample>, Error> {
+    unsafe fn snapshot_threads(
+        &self,
+        tick: u64,
+        start_time: std::time::Instant,
+    ) -> Result<Vec<RawSample>, Error> {
         let mut snapshot = Vec::new();
 
         for thread_handle in self.thread_iter() {
             let thread_id = GetThreadId(thread_handle);
             if let Ok(backtrace) = Backtrace::backtrace(*self.process_handle, thread_handle) {
+                let timestamp = start_time.elapsed();
                 let (user_cpu_time, kernel_cpu_time) = self.thread_cpu_time(thread_handle);
 
                 snapshot.push(RawSample::new(
                     thread_id,
+                    tick,
+                    timestamp,
                     user_cpu_time,
                     kernel_cpu_time,
                     backtrace.map(|frame| frame.AddrPC.Offset).collect(),
@@ -185,123 +267,154 @@ impl Sampler {
     }
 }
 
-/// Sample all the threads of the specified process at the specified interval.
-pub fn profile(pid: Pid, duration: Duration, interval: Duration) -> Result<ProcessSample, Error> {
-    let sampler = Sampler::attach(pid)?;
+/// Sample all the threads of the specified process at the specified interval,
+/// until the duration elapses or the process exits. The timeline of each thread is only
+/// recorded when `keep_timelines` is set, as it grows with every sample.
+pub fn profile(
+    pid: Pid,
+    duration: Duration,
+    interval: Duration,
+    keep_timelines: bool,
+) -> Result<ProcessSample, Error> {
+    let capture = ProcessCapture::start(Sampler::attach(pid)?, pid, false, keep_timelines)?;
+    Ok(sample_processes(vec![capture], None, duration, interval).remove(0))
+}
 
-    let modules = sampler.loaded_modules();
-    let search_path: HashSet<&str> = modules.iter().flat_map(ModuleInfo::module_dir).collect();
-    let symbolicator = Symbolicator::new(*sampler.process_handle, &Vec::from_iter(search_path))?;
+/// Launch the specified command line and sample it from its first instruction,
+/// until the duration elapses or the process exits.
+pub fn profile_command(
+    command: &[String],
+    duration: Duration,
+    interval: Duration,
+    keep_timelines: bool,
+) -> Result<ProcessSample, Error> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| Error::LaunchFailed(std::io::ErrorKind::InvalidInput.into()))?;
+    let mut child = std::process::Command::new(program)
+        .args(args)
+        .creation_flags(CREATE_SUSPENDED.0)
+        .spawn()
+        .map_err(Error::LaunchFailed)?;
+
+    let capture = match Sampler::attach(child.id())
+        .and_then(|sampler| ProcessCapture::start(sampler, child.id(), true, keep_timelines))
+    {
+        Ok(capture) => capture,
+        Err(error) => {
+            _ = child.kill();
+            return Err(error);
+        }
+    };
+    Ok(sample_processes(vec![capture], None, duration, interval).remove(0))
+}
 
-    let exe_file = sampler.exe();
+/// Sample the specified processes concurrently on a shared timeline, optionally along with all of
+/// their descendants, including the ones created during the capture, until the duration elapses
+/// or all of them exit.
+pub fn profile_processes(
+    pids: &[Pid],
+    include_descendants: bool,
+    duration: Duration,
+    interval: Duration,
+    keep_timelines: bool,
+) -> Result<Vec<ProcessSample>, Error> {
+    let mut captures = Vec::new();
+    for &pid in pids {
+        captures.push(ProcessCapture::start(
+            Sampler::attach(pid)?,
+            pid,
+            false,
+            keep_timelines,
+        )?);
+    }
+    let watcher = include_descendants.then(|| ProcessTreeWatcher::spawn(pids, keep_timelines));
+    Ok(sample_processes(captures, watcher, duration, interval))
+}
 
+/// Sample the specified processes, along with the descendants found by the watcher,
+/// until the duration elapses or all of them exit.
+fn sample_processes(
+    mut captures: Vec<ProcessCapture>,
+    watcher: Option<ProcessTreeWatcher>,
+    duration: Duration,
+    interval: Duration,
+) -> Vec<ProcessSample> {
     let cancel_status = CancelStatus::new();
     cancel_status.activate_ctrl_c_handler();
 
-    println!(
-        "Sampling process: {} - {} for {} {} with {} millisecond interval",
-        pid,
-        exe_file
-            .as_ref()
-            .map(|p| p.to_str())
-            .flatten()
-            .unwrap_or("{unknown}"),
-        duration.as_secs(),
-        match duration.as_secs() {
-            1 => "second",
-            _ => "seconds",
-        },
-        interval.as_millis()
-    );
-
-    let (before_user_time, before_kernel_time) = sampler.process_cpu_time();
-
-    // Take backtrace snapshots of all threads in the specified process.
+    for capture in &captures {
+        println!(
+            "Sampling process: {} - {} {} with {} millisecond interval",
+            capture.get_pid(),
+            capture.get_exe_name(),
+            match duration.as_secs() {
+                _ if duration == Duration::MAX => "until it exits".to_string(),
+                1 => "for 1 second".to_string(),
+                seconds => format!("for {} seconds", seconds),
+            },
+            interval.as_millis()
+        );
+    }
+
+    // Take backtrace snapshots of all threads in the specified processes.
     let start_time = std::time::Instant::now();
-    let mut raw_samples = Vec::new();
+    let mut tick = 0;
+    for capture in &captures {
+        capture.resume();
+    }
     while start_time.elapsed() < duration {
         if cancel_status.is_canceled() {
             println!("^C [interrupted]");
             break;
         }
+
+        for capture in watcher
+            .as_ref()
+            .map(ProcessTreeWatcher::new_captures)
+            .unwrap_or_default()
+        {
+            println!(
+                "Sampling child process: {} - {}",
+                capture.get_pid(),
+                capture.get_exe_name()
+            );
+            captures.push(capture);
+        }
+
+        for capture in captures.iter_mut() {
+            if let Some(exit_code) = capture.update_exit_code() {
+                println!(
+                    "Process {} exited with code {}",
+                    capture.get_pid(),
+                    exit_code
+                );
+            }
+        }
+        if captures.iter().all(ProcessCapture::has_exited) {
+            break;
+        }
+
         run_and_yield_for_duration(interval, || {
-            if let Ok(mut snapshot) = unsafe { sampler.snapshot_threads() } {
-                raw_samples.append(&mut snapshot);
+            for capture in captures.iter_mut().filter(|capture| !capture.has_exited()) {
+                capture.snapshot(tick, start_time);
             }
         });
+        tick += 1;
     }
-
-    let (after_user_time, after_kernel_time) = sampler.process_cpu_time();
+    // Children the watcher is still attaching would initialize DbgHelp while the captures
+    // symbolicate, so stop it first; captures it had not handed over yet are dropped.
+    drop(watcher);
 
     println!("Symbolicating...");
-    let symbol_table =
-        raw_samples
-            .iter()
-            .fold(SymbolTable::new(), |mut symbol_table, raw_sample| {
-                symbol_table.symbolicate(raw_sample.get_backtrace(), &symbolicator);
-                symbol_table
-            });
-
-    println!("Building sample tree...");
-
-    // Sort all raw samples by thread, then iterate through them grouped by thread
-    // to produce the final sample tree for each thread.
-    raw_samples.sort_by(|a, b| a.get_thread_id().cmp(&b.get_thread_id()));
-    let threads = raw_samples
-        .chunk_by(|a, b| a.get_thread_id() == b.get_thread_id())
-        .map(|raw_thread_samples| {
-            let thread_id = raw_thread_samples
-                .first()
-                .map(|raw_sample| raw_sample.get_thread_id())
-                .unwrap();
-
-            let min_user_cpu_time = raw_thread_samples
-                .iter()
-                .map(RawSample::get_user_cpu_time)
-                .min()
-                .unwrap_or_default();
-            let max_user_cpu_time = raw_thread_samples
-                .iter()
-                .map(RawSample::get_user_cpu_time)
-                .max()
-                .unwrap_or_default();
-
-            let min_kernel_cpu_time = raw_thread_samples
-                .iter()
-                .map(RawSample::get_kernel_cpu_time)
-                .min()
-                .unwrap_or_default();
-            let max_kernel_cpu_time = raw_thread_samples
-                .iter()
-                .map(RawSample::get_kernel_cpu_time)
-                .max()
-                .unwrap_or_default();
-
-            let mut thread_sample = ThreadSample::new(
-                thread_id,
-                min_user_cpu_time.abs_diff(max_user_cpu_time),
-                min_kernel_cpu_time.abs_diff(max_kernel_cpu_time),
-            );
-            for raw_sample in raw_thread_samples {
-                thread_sample.add_backtrace(raw_sample.get_backtrace().iter().rev());
-            }
-            thread_sample
-        })
+    let process_samples = captures
+        .into_iter()
+        .map(|capture| capture.finish(interval))
         .collect();
 
     println!();
 
-    Ok(ProcessSample::new(
-        ProcessInfo::new(
-            pid,
-            exe_file.unwrap_or_default(),
-            modules,
-            after_user_time.abs_diff(before_user_time),
-            after_kernel_time.abs_diff(before_kernel_time),
-        ),
-        threads,
-        symbol_table,
-    ))
+    process_samples
 }
 
 fn run_and_yield_for_duration<F: FnMut()>(duration: Duration, mut f: F) {
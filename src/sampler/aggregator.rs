@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::time::Duration;
+
+use super::*;
+
+/// Folds raw samples into the sample tree and timeline of their thread as they are taken,
+/// so the raw samples don't need to be kept around until the end of the capture.
+/// The backtraces of the timelines are interned in a stack table shared by all threads.
+///
+/// The timelines grow with every sample, so they are only kept when asked for. Without them,
+/// memory is bounded by the number of unique stacks.
+#[derive(Debug, Default)]
+pub struct SampleAggregator {
+    threads: BTreeMap<Tid, ThreadAggregate>,
+    stack_table: StackTable,
+    keep_timelines: bool,
+}
+
+#[derive(Debug)]
+struct ThreadAggregate {
+    thread_sample: ThreadSample,
+    timeline: ThreadTimeline,
+    user_cpu_time: Range<Duration>,
+    kernel_cpu_time: Range<Duration>,
+}
+
+impl SampleAggregator {
+    pub fn new(keep_timelines: bool) -> Self {
+        Self {
+            keep_timelines,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_sample(&mut self, raw_sample: RawSample) {
+        let thread_id = raw_sample.get_thread_id();
+        let user_cpu_time = raw_sample.get_user_cpu_time();
+        let kernel_cpu_time = raw_sample.get_kernel_cpu_time();
+
+        let thread = self
+            .threads
+            .entry(thread_id)
+            .or_insert_with(|| ThreadAggregate {
+                thread_sample: ThreadSample::new(thread_id, Duration::ZERO, Duration::ZERO),
+                timeline: ThreadTimeline::new(thread_id),
+                user_cpu_time: user_cpu_time..user_cpu_time,
+                kernel_cpu_time: kernel_cpu_time..kernel_cpu_time,
+            });
+        thread.user_cpu_time.start = thread.user_cpu_time.start.min(user_cpu_time);
+        thread.user_cpu_time.end = thread.user_cpu_time.end.max(user_cpu_time);
+        thread.kernel_cpu_time.start = thread.kernel_cpu_time.start.min(kernel_cpu_time);
+        thread.kernel_cpu_time.end = thread.kernel_cpu_time.end.max(kernel_cpu_time);
+
+        let stack_id = self
+            .stack_table
+            .intern(raw_sample.get_backtrace().iter().rev().copied());
+        thread
+            .thread_sample
+            .add_backtrace(raw_sample.get_backtrace().iter().rev());
+        if self.keep_timelines {
+            thread.timeline.add_sample(TimelineSample::new(
+                raw_sample.get_tick(),
+                raw_sample.get_timestamp(),
+                user_cpu_time,
+                kernel_cpu_time,
+                stack_id,
+            ));
+        }
+    }
+
+    /// The stacks of all backtraces added so far.
+    pub fn get_stack_table(&self) -> &StackTable {
+        &self.stack_table
+    }
+
+    /// The sample tree and timeline of each thread, ordered by thread id, along with the stack table
+    /// of the timelines. The CPU times of each thread are the CPU times it consumed between its
+    /// first and last sample. There are no timelines when they weren't kept.
+    pub fn finish(self) -> (Vec<ThreadSample>, Vec<ThreadTimeline>, StackTable) {
+        let (threads, timelines) = self
+            .threads
+            .into_values()
+            .map(|mut thread| {
+                thread.thread_sample.set_cpu_time(
+                    thread.user_cpu_time.end - thread.user_cpu_time.start,
+                    thread.kernel_cpu_time.end - thread.kernel_cpu_time.start,
+                );
+                (thread.thread_sample, thread.timeline)
+            })
+            .unzip();
+        let timelines = if self.keep_timelines {
+            timelines
+        } else {
+            Vec::new()
+        };
+        (threads, timelines, self.stack_table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw_sample(thread_id: Tid, tick: u64, cpu_time: u64, backtrace: &[u64]) -> RawSample {
+        RawSample::new(
+            thread_id,
+            tick,
+            Duration::from_millis(tick),
+            Duration::from_millis(cpu_time),
+            Duration::default(),
+            backtrace.to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_samples_folded_by_thread() {
+        let mut aggregator = SampleAggregator::new(true);
+        aggregator.add_sample(raw_sample(2, 0, 5, &[3, 1]));
+        aggregator.add_sample(raw_sample(1, 0, 0, &[2, 1]));
+        aggregator.add_sample(raw_sample(2, 1, 7, &[3, 1]));
+        aggregator.add_sample(raw_sample(1, 1, 4, &[1]));
+
+        assert_eq!(aggregator.get_stack_table().get_frames().len(), 3);
+
+        let (threads, timelines, stack_table) = aggregator.finish();
+        assert_eq!(
+            threads
+                .iter()
+                .map(|thread| (
+                    thread.get_thread_id(),
+                    thread.get_sample_count(),
+                    thread.get_user_cpu_time()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, 2, Duration::from_millis(4)),
+                (2, 2, Duration::from_millis(2))
+            ]
+        );
+        assert_eq!(
+            stack_table.backtrace(timelines[0].get_samples()[0].get_stack_id()),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn test_memory_flat_for_repeated_stacks() {
+        let mut aggregator = SampleAggregator::new(false);
+        for tick in 0..10_000 {
+            aggregator.add_sample(raw_sample(1, tick, tick, &[3, 2, 1]));
+        }
+
+        assert_eq!(aggregator.get_stack_table().get_frames().len(), 3);
+        assert!(aggregator
+            .threads
+            .values()
+            .all(|thread| thread.timeline.get_samples().is_empty()));
+
+        let (threads, timelines, _) = aggregator.finish();
+        assert!(timelines.is_empty());
+        assert_eq!(threads[0].get_sample_count(), 10_000);
+        assert_eq!(threads[0].sample_tree_dfs_iter().count(), 3);
+    }
+}
@@ -51,6 +51,9 @@ impl Iterator for Backtrace {
     type Item = STACKFRAME64;
 
     fn next(&mut self) -> Option<Self::Item> {
+        // The symbolication worker only holds the lock for one address at a time, so the thread
+        // isn't kept suspended for long.
+        let _dbghelp_lock = dbghelp_lock();
         unsafe {
             if StackWalk64(
                 IMAGE_FILE_MACHINE_AMD64.0 as u32,
@@ -59,8 +62,8 @@ impl Iterator for Backtrace {
                 &mut self.current_frame,
                 &raw mut self.current_context as *mut c_void,
                 None,
-                None,
-                None,
+                Some(Some(function_tables::function_table_access)),
+                Some(Some(function_tables::image_base)),
                 None,
             ) == TRUE
             {
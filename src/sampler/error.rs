@@ -3,6 +3,7 @@ pub enum Error {
     AttachProcessFailed(windows::core::Error),
     SymInitializeFailed(windows::core::Error),
     BacktraceFailed(windows::core::Error),
+    LaunchFailed(std::io::Error),
 }
 
 impl std::error::Error for Error {
@@ -11,6 +12,7 @@ impl std::error::Error for Error {
             Self::AttachProcessFailed(inner_error) => Some(inner_error),
             Self::SymInitializeFailed(inner_error) => Some(inner_error),
             Self::BacktraceFailed(inner_error) => Some(inner_error),
+            Self::LaunchFailed(inner_error) => Some(inner_error),
         }
     }
 }
@@ -29,6 +31,9 @@ impl std::fmt::Display for Error {
             Self::BacktraceFailed(inner_error) => {
                 write!(f, "unable to capture backtrace: {}", inner_error)
             }
+            Self::LaunchFailed(inner_error) => {
+                write!(f, "unable to launch the specified command: {}", inner_error)
+            }
         }
     }
 }
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use windows::Win32::System::Memory::*;
+
+use super::*;
+
+/// Entry of the exception directory of an x64 image, with addresses relative to the image base.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct RuntimeFunction {
+    begin_address: u32,
+    end_address: u32,
+    unwind_data: u32,
+}
+
+/// Function tables read so far, by process handle and image base, sorted by begin address.
+type FunctionTables = BTreeMap<(usize, u64), Vec<RuntimeFunction>>;
+
+fn function_tables() -> MutexGuard<'static, FunctionTables> {
+    static FUNCTION_TABLES: Mutex<FunctionTables> = Mutex::new(BTreeMap::new());
+    FUNCTION_TABLES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Function table access routine of `StackWalk64`, which reads the unwind information straight
+/// from the images of the process instead of going through the DbgHelp symbol handler, so stack
+/// walks don't have to load the symbols of the modules.
+///
+/// Addresses outside of x64 images, such as JIT code or 32-bit modules, have no function entry and
+/// are unwound as leaf functions.
+pub unsafe extern "system" fn function_table_access(
+    process_handle: HANDLE,
+    address: u64,
+) -> *mut c_void {
+    let image_base = image_base(process_handle, address);
+    if image_base == 0 {
+        return std::ptr::null_mut();
+    }
+
+    // The entries stay in place until the process is forgotten, after its capture is finished.
+    let mut function_tables = function_tables();
+    let functions = function_tables
+        .entry((process_handle.0 as usize, image_base))
+        .or_insert_with(|| read_function_table(process_handle, image_base).unwrap_or_default());
+    let offset = (address - image_base) as u32;
+    let index = functions.partition_point(|function| function.end_address <= offset);
+    match functions.get(index) {
+        Some(function) if function.begin_address <= offset => {
+            function as *const RuntimeFunction as *mut c_void
+        }
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// Module base routine of `StackWalk64`, which finds the image containing the address from the
+/// memory mappings of the process.
+pub unsafe extern "system" fn image_base(process_handle: HANDLE, address: u64) -> u64 {
+    let mut memory_info = MEMORY_BASIC_INFORMATION::default();
+    if VirtualQueryEx(
+        process_handle,
+        Some(address as *const c_void),
+        &mut memory_info,
+        size_of::<MEMORY_BASIC_INFORMATION>(),
+    ) == 0
+        || memory_info.Type != MEM_IMAGE
+    {
+        return 0;
+    }
+    memory_info.AllocationBase as u64
+}
+
+/// Drop the function tables read for the process, before its handle is closed and may be reused.
+pub fn forget_process(process_handle: HANDLE) {
+    function_tables().retain(|&(handle, _), _| handle != process_handle.0 as usize);
+}
+
+unsafe fn read_function_table(
+    process_handle: HANDLE,
+    image_base: u64,
+) -> Option<Vec<RuntimeFunction>> {
+    let nt_headers_offset: u32 = read_process_memory(process_handle, image_base + 0x3c)?;
+    let nt_headers: IMAGE_NT_HEADERS64 =
+        read_process_memory(process_handle, image_base + nt_headers_offset as u64)?;
+    // The headers of 32-bit images are laid out differently, and they have no exception directory.
+    if nt_headers.OptionalHeader.Magic != IMAGE_NT_OPTIONAL_HDR64_MAGIC {
+        return None;
+    }
+    let exception_directory =
+        nt_headers.OptionalHeader.DataDirectory[IMAGE_DIRECTORY_ENTRY_EXCEPTION.0 as usize];
+
+    let mut functions = vec![
+        RuntimeFunction::default();
+        exception_directory.Size as usize / size_of::<RuntimeFunction>()
+    ];
+    ReadProcessMemory(
+        process_handle,
+        (image_base + exception_directory.VirtualAddress as u64) as *const c_void,
+        functions.as_mut_ptr() as *mut c_void,
+        functions.len() * size_of::<RuntimeFunction>(),
+        None,
+    )
+    .ok()?;
+    Some(functions)
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use super::*;
+
+/// Minimum time between reads of the map file, for addresses that aren't in it yet.
+const MAP_REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Names of JIT compiled functions, read from a map file in the format of perf maps, which has a
+/// `START SIZE name` line for each function, with the start address and size in hex.
+///
+/// JITs keep appending to the file as they compile functions, so the lines added since the last
+/// read are picked up when an address isn't found.
+pub struct JitSymbolMap {
+    path: PathBuf,
+    /// Length of the complete lines read so far.
+    read_length: u64,
+    last_refresh: Option<Instant>,
+    /// End address and name of each function, by start address.
+    functions: BTreeMap<u64, (u64, String)>,
+}
+
+impl JitSymbolMap {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            read_length: 0,
+            last_refresh: None,
+            functions: BTreeMap::new(),
+        }
+    }
+
+    /// The map file of the specified process, `perf-<pid>.map` in the temporary directory.
+    pub fn for_process(pid: Pid) -> Self {
+        Self::new(std::env::temp_dir().join(format!("perf-{}.map", pid)))
+    }
+
+    /// The address range and name of the function containing the address.
+    pub fn function(&mut self, address: u64) -> Option<(Range<u64>, &str)> {
+        if self.lookup(address).is_none() {
+            self.refresh();
+        }
+        self.lookup(address)
+    }
+
+    fn lookup(&self, address: u64) -> Option<(Range<u64>, &str)> {
+        self.functions
+            .range(..=address)
+            .next_back()
+            .filter(|(_, (end, _))| address < *end)
+            .map(|(start, (end, name))| (*start..*end, name.as_str()))
+    }
+
+    /// Read the lines appended since the last refresh, at most once per refresh interval.
+    fn refresh(&mut self) {
+        if self
+            .last_refresh
+            .is_some_and(|last_refresh| last_refresh.elapsed() < MAP_REFRESH_INTERVAL)
+        {
+            return;
+        }
+        self.last_refresh = Some(Instant::now());
+
+        let Ok(mut file) = File::open(&self.path) else {
+            return;
+        };
+        if file.seek(SeekFrom::Start(self.read_length)).is_err() {
+            return;
+        }
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        while reader.read_line(&mut line).is_ok_and(|length| length > 0) {
+            // The JIT may still be writing the last line.
+            if !line.ends_with('\n') {
+                break;
+            }
+            self.read_length += line.len() as u64;
+            if let Some((range, name)) = parse_line(&line) {
+                self.functions
+                    .insert(range.start, (range.end, name.to_string()));
+            }
+            line.clear();
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Option<(Range<u64>, &str)> {
+    let mut fields = line.trim_end().splitn(3, ' ');
+    let mut hex_field = || u64::from_str_radix(fields.next()?.trim_start_matches("0x"), 16).ok();
+    let start = hex_field()?;
+    let size = hex_field()?;
+    let name = fields.next().filter(|name| !name.is_empty())?;
+    Some((start..start + size, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_appended_functions_picked_up() {
+        let path = std::env::temp_dir().join(format!(
+            "spinsample-jit-symbol-map-test-{}.map",
+            std::process::id()
+        ));
+        let mut file = File::create(&path).unwrap();
+        write!(file, "1000 20 LazyCompile:*main app.js:1\n0x2000 10 Int").unwrap();
+
+        let mut jit_symbol_map = JitSymbolMap::new(path.clone());
+        assert_eq!(
+            jit_symbol_map.function(0x1010),
+            Some((0x1000..0x1020, "LazyCompile:*main app.js:1"))
+        );
+        assert_eq!(jit_symbol_map.function(0x1020), None);
+        assert_eq!(jit_symbol_map.function(0x2000), None);
+
+        writeln!(file, "erpreter").unwrap();
+        jit_symbol_map.last_refresh = None;
+        let function = jit_symbol_map.function(0x2008);
+        _ = std::fs::remove_file(&path);
+
+        assert_eq!(function, Some((0x2000..0x2010, "Interpreter")));
+    }
+}
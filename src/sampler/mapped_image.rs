@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+
+use windows::Win32::Storage::FileSystem::*;
+use windows::Win32::System::ProcessStatus::*;
+
+use super::*;
+
+/// Maps the NT device names of volumes, such as `\Device\HarddiskVolume3`, to their drive letters.
+pub struct DosDevices {
+    /// Drive letter, such as `C:`, and NT device name of each volume that has one.
+    devices: Vec<(String, String)>,
+}
+
+impl DosDevices {
+    pub fn query() -> Self {
+        let mut drives = [0u16; 128];
+        let length = unsafe { GetLogicalDriveStringsW(Some(&mut drives)) } as usize;
+        let devices = drives[..length.min(drives.len())]
+            .split(|&c| c == 0)
+            .filter_map(|drive| {
+                // Drives are listed as `C:\`, devices are queried as `C:`.
+                let drive = String::from_utf16(drive).ok()?;
+                let drive = drive.trim_end_matches('\\');
+                let device_name: Vec<u16> = drive.encode_utf16().chain([0]).collect();
+                let mut device = [0u16; MAX_PATH as usize];
+                let length = unsafe {
+                    QueryDosDeviceW(PCWSTR::from_raw(device_name.as_ptr()), Some(&mut device))
+                } as usize;
+                // The first of the NUL terminated device names is the current one.
+                let device = device[..length].split(|&c| c == 0).next()?;
+                Some((drive.to_string(), String::from_utf16(device).ok()?))
+            })
+            .filter(|(drive, device)| !drive.is_empty() && !device.is_empty())
+            .collect();
+        Self { devices }
+    }
+
+    /// The Win32 path of a file given by its NT path. Files on volumes without a drive letter,
+    /// such as the volumes of containers, are reached through the NT namespace.
+    fn win32_path(&self, nt_path: &str) -> PathBuf {
+        self.devices
+            .iter()
+            .find_map(|(drive, device)| {
+                let path = nt_path.strip_prefix(device.as_str())?;
+                path.starts_with('\\')
+                    .then(|| PathBuf::from(format!("{}{}", drive, path)))
+            })
+            .unwrap_or_else(|| PathBuf::from(format!("\\\\?\\GLOBALROOT{}", nt_path)))
+    }
+}
+
+/// The path of the image file mapped at the base address of a module, as seen by the sampler,
+/// when it differs from the path the process reports for the module.
+///
+/// The process reports the path it loaded the module from, which is a path within the container
+/// for processes running in one, and which no longer leads to the loaded image when the file was
+/// renamed since, such as when an in-place upgrade moves a running binary aside.
+pub unsafe fn host_path(
+    process_handle: HANDLE,
+    base_address: u64,
+    reported_path: &str,
+    dos_devices: &DosDevices,
+) -> Option<PathBuf> {
+    let mut nt_path = [0u16; MAX_PATH as usize];
+    let length =
+        GetMappedFileNameW(process_handle, base_address as *const c_void, &mut nt_path) as usize;
+    if length == 0 {
+        return None;
+    }
+    let path = dos_devices.win32_path(&String::from_utf16(&nt_path[..length]).ok()?);
+    path.to_str()
+        .is_some_and(|path| !path.eq_ignore_ascii_case(reported_path))
+        .then_some(path)
+}
+
+/// Record the host paths of the modules whose image file isn't at the path the process reports.
+pub fn add_host_paths(process_handle: HANDLE, modules: Vec<ModuleInfo>) -> Vec<ModuleInfo> {
+    let dos_devices = DosDevices::query();
+    modules
+        .into_iter()
+        .map(|module| {
+            let host_path = unsafe {
+                host_path(
+                    process_handle,
+                    module.address_range().start,
+                    module.file_path().unwrap_or_default(),
+                    &dos_devices,
+                )
+            };
+            module.with_host_path(host_path)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_win32_path() {
+        let dos_devices = DosDevices {
+            devices: vec![
+                ("C:".to_string(), "\\Device\\HarddiskVolume3".to_string()),
+                ("D:".to_string(), "\\Device\\HarddiskVolume1".to_string()),
+            ],
+        };
+        assert_eq!(
+            dos_devices.win32_path("\\Device\\HarddiskVolume1\\app\\app.exe.old"),
+            PathBuf::from("D:\\app\\app.exe.old")
+        );
+        assert_eq!(
+            dos_devices.win32_path("\\Device\\HarddiskVolume30\\app\\app.exe"),
+            PathBuf::from("\\\\?\\GLOBALROOT\\Device\\HarddiskVolume30\\app\\app.exe")
+        );
+    }
+}
@@ -1,10 +1,13 @@
 use std::path::PathBuf;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ModuleInfo {
     path: PathBuf,
     base_address: u64,
     size: u32,
+    /// Path of the image file as seen by the sampler, when it isn't at the path the process
+    /// reports, such as for processes in containers.
+    host_path: Option<PathBuf>,
 }
 
 impl ModuleInfo {
@@ -13,9 +16,19 @@ impl ModuleInfo {
             path,
             base_address,
             size,
+            host_path: None,
         }
     }
 
+    pub fn with_host_path(mut self, host_path: Option<PathBuf>) -> Self {
+        self.host_path = host_path;
+        self
+    }
+
+    pub fn host_file_path(&self) -> Option<&str> {
+        self.host_path.as_deref()?.to_str()
+    }
+
     pub fn name(&self) -> Option<&str> {
         self.path.file_name().map(std::ffi::OsStr::to_str).flatten()
     }
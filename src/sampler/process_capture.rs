@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::*;
+
+/// Capture of a single process, from attaching to it until its process sample is complete.
+pub struct ProcessCapture {
+    sampler: Sampler,
+    pid: Pid,
+    parent_pid: Option<Pid>,
+    is_launched: bool,
+    exe_file: Option<PathBuf>,
+    modules: Vec<ModuleInfo>,
+    symbolication_worker: SymbolicationWorker,
+    aggregator: SampleAggregator,
+    symbolicated_frame_count: usize,
+    before_user_time: Duration,
+    before_kernel_time: Duration,
+    exit_code: Option<u32>,
+}
+
+impl ProcessCapture {
+    /// Prepare the capture of the process the sampler is attached to. A launched process is expected
+    /// to be suspended until `resume` is called. The timelines of the threads are only recorded when
+    /// `keep_timelines` is set.
+    pub fn start(
+        sampler: Sampler,
+        pid: Pid,
+        is_launched: bool,
+        keep_timelines: bool,
+    ) -> Result<Self, Error> {
+        // A launched process hasn't loaded its modules yet, the symbolicator picks them up as they load.
+        let modules = sampler.loaded_modules();
+        let exe_file = sampler.exe();
+        let search_path: HashSet<&str> = modules
+            .iter()
+            .flat_map(ModuleInfo::module_dir)
+            .chain(
+                exe_file
+                    .as_deref()
+                    .and_then(Path::parent)
+                    .and_then(Path::to_str),
+            )
+            .collect();
+        let symbolicator = Symbolicator::new(
+            *sampler.process_handle,
+            &Vec::from_iter(search_path),
+            !is_launched,
+        )?;
+
+        let (before_user_time, before_kernel_time) = sampler.process_cpu_time();
+
+        Ok(Self {
+            sampler,
+            pid,
+            parent_pid: None,
+            is_launched,
+            exe_file,
+            modules,
+            // Start from the functions that previous captures resolved in the same builds of the modules.
+            symbolication_worker: SymbolicationWorker::spawn(
+                symbolicator,
+                SymbolTable::new(),
+                SymbolCache::open_default(),
+            ),
+            aggregator: SampleAggregator::new(keep_timelines),
+            symbolicated_frame_count: 0,
+            before_user_time,
+            before_kernel_time,
+            exit_code: None,
+        })
+    }
+
+    pub fn get_pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn set_parent_pid(&mut self, parent_pid: Pid) {
+        self.parent_pid = Some(parent_pid);
+    }
+
+    /// The path of the executable of the process, for display.
+    pub fn get_exe_name(&self) -> &str {
+        self.exe_file
+            .as_ref()
+            .and_then(|path| path.to_str())
+            .unwrap_or("{unknown}")
+    }
+
+    /// Resume a launched process, so it runs its first instruction.
+    pub fn resume(&self) {
+        if self.is_launched {
+            self.sampler.resume_threads();
+        }
+    }
+
+    /// Check whether the process has exited, returning its exit code when it just did.
+    pub fn update_exit_code(&mut self) -> Option<u32> {
+        if self.exit_code.is_some() {
+            return None;
+        }
+        self.exit_code = self.sampler.exit_code();
+        self.exit_code
+    }
+
+    pub fn has_exited(&self) -> bool {
+        self.exit_code.is_some()
+    }
+
+    /// Take a backtrace snapshot of all threads, symbolicating the addresses of new stacks
+    /// in the background as they are seen.
+    pub fn snapshot(&mut self, tick: u64, start_time: Instant) {
+        if let Ok(snapshot) = unsafe { self.sampler.snapshot_threads(tick, start_time) } {
+            for raw_sample in snapshot {
+                self.aggregator.add_sample(raw_sample);
+            }
+        }
+
+        let frames = self.aggregator.get_stack_table().get_frames();
+        self.symbolication_worker.symbolicate(Vec::from_iter(
+            frames[self.symbolicated_frame_count..]
+                .iter()
+                .map(StackFrame::get_address),
+        ));
+        self.symbolicated_frame_count = frames.len();
+    }
+
+    /// Wait for the symbolication of all samples, and build the process sample.
+    pub fn finish(self, interval: Duration) -> ProcessSample {
+        let (after_user_time, after_kernel_time) = self.sampler.process_cpu_time();
+
+        let (symbol_table, symbolicator) = self.symbolication_worker.finish();
+
+        // The modules of an exited process can only be listed from what the symbolicator has seen.
+        let process_modules = match (self.is_launched, self.exit_code) {
+            (false, _) => self.modules,
+            (true, None) => self.sampler.loaded_modules(),
+            (true, Some(_)) => symbolicator.loaded_modules(),
+        };
+        drop(symbolicator);
+
+        let (threads, timelines, stack_table) = self.aggregator.finish();
+
+        let mut process_info = ProcessInfo::new(
+            self.pid,
+            self.exe_file.unwrap_or_default(),
+            process_modules,
+            after_user_time.abs_diff(self.before_user_time),
+            after_kernel_time.abs_diff(self.before_kernel_time),
+        );
+        process_info.parent_pid = self.parent_pid;
+        process_info.exit_code = self.exit_code;
+
+        ProcessSample::new(
+            process_info,
+            threads,
+            timelines,
+            stack_table,
+            symbol_table,
+            interval,
+        )
+    }
+}
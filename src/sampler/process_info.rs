@@ -8,6 +8,10 @@ pub struct ProcessInfo {
     pub modules: Vec<ModuleInfo>,
     pub user_cpu_time: Duration,
     pub kernel_cpu_time: Duration,
+    /// Pid of the parent process, when the process was sampled as part of a process tree.
+    pub parent_pid: Option<Pid>,
+    /// Exit code of the process, when it exited during the capture.
+    pub exit_code: Option<u32>,
 }
 
 impl ProcessInfo {
@@ -24,6 +28,8 @@ impl ProcessInfo {
             modules,
             user_cpu_time,
             kernel_cpu_time,
+            parent_pid: None,
+            exit_code: None,
         }
     }
 }
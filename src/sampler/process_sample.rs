@@ -1,24 +1,133 @@
+use std::ops::Range;
+use std::time::Duration;
+
 use super::*;
 
 #[derive(Debug)]
 pub struct ProcessSample {
     pub process_info: ProcessInfo,
     threads: Vec<ThreadSample>,
+    timelines: Vec<ThreadTimeline>,
+    stack_table: StackTable,
     symbol_table: SymbolTable,
+    interval: Duration,
+    capture_count: u32,
 }
 
 impl ProcessSample {
     pub fn new(
         process_info: ProcessInfo,
         threads: Vec<ThreadSample>,
+        timelines: Vec<ThreadTimeline>,
+        stack_table: StackTable,
         symbol_table: SymbolTable,
+        interval: Duration,
     ) -> Self {
         Self {
             process_info,
             threads,
+            timelines,
+            stack_table,
             symbol_table,
+            interval,
+            capture_count: 1,
         }
     }
+
+    pub fn get_threads(&self) -> &[ThreadSample] {
+        &self.threads
+    }
+
+    pub fn get_timelines(&self) -> &[ThreadTimeline] {
+        &self.timelines
+    }
+
+    /// The stacks referred to by the samples of the timelines.
+    pub fn get_stack_table(&self) -> &StackTable {
+        &self.stack_table
+    }
+
+    pub fn get_symbol_table(&self) -> &SymbolTable {
+        &self.symbol_table
+    }
+
+    /// The sampling interval the capture was taken with.
+    pub fn get_interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The number of captures this sample was merged from.
+    pub fn get_capture_count(&self) -> u32 {
+        self.capture_count
+    }
+
+    pub fn set_capture_count(&mut self, capture_count: u32) {
+        self.capture_count = capture_count;
+    }
+
+    /// The time covered by the timelines, assuming the last sample lasts for one interval.
+    pub fn get_duration(&self) -> Duration {
+        self.timelines
+            .iter()
+            .filter_map(|timeline| timeline.get_samples().last())
+            .map(|sample| sample.get_timestamp() + self.interval)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Build the sample trees of the threads from the samples taken within the specified
+    /// time range. Threads without a timeline aren't included.
+    pub fn window_threads(&self, range: &Range<Duration>) -> Vec<ThreadSample> {
+        self.timelines
+            .iter()
+            .map(|timeline| timeline.thread_sample(range, &self.stack_table))
+            .collect()
+    }
+
+    /// Restrict the sample to the samples taken within the specified time range,
+    /// rebuilding the sample trees and CPU times from the timelines.
+    pub fn slice(&mut self, range: &Range<Duration>) {
+        self.threads = self.window_threads(range);
+        for timeline in &mut self.timelines {
+            timeline.retain_window(range);
+        }
+        self.process_info.user_cpu_time = self
+            .threads
+            .iter()
+            .map(ThreadSample::get_user_cpu_time)
+            .sum();
+        self.process_info.kernel_cpu_time = self
+            .threads
+            .iter()
+            .map(ThreadSample::get_kernel_cpu_time)
+            .sum();
+    }
+
+    /// Remove all threads, and their timelines, that don't match the specified predicate.
+    pub fn retain_threads(&mut self, mut predicate: impl FnMut(Tid) -> bool) {
+        self.threads
+            .retain(|thread| predicate(thread.get_thread_id()));
+        self.timelines
+            .retain(|timeline| predicate(timeline.get_thread_id()));
+    }
+
+    /// Replace the sample tree of each thread with its inverted tree.
+    pub fn invert(&mut self) {
+        self.threads = self.threads.iter().map(ThreadSample::inverted).collect();
+    }
+
+    /// Remove the nodes of each thread's sample tree that account for less than
+    /// the specified percentage of the thread's samples.
+    pub fn prune(&mut self, min_percent: f64) {
+        self.threads = self
+            .threads
+            .iter()
+            .map(|thread| {
+                let min_count = (thread.get_sample_count() as f64 * min_percent / 100.0).ceil();
+                thread.pruned(min_count as u32)
+            })
+            .collect();
+    }
 }
 
 impl std::fmt::Display for ProcessSample {
@@ -40,6 +149,15 @@ impl std::fmt::Display for ProcessSample {
             user_cpu_time.as_secs_f64(),
             kernel_cpu_time.as_secs_f64()
         )?;
+        if let Some(parent_pid) = self.process_info.parent_pid {
+            writeln!(f, "  Parent Pid: {}", parent_pid)?;
+        }
+        if let Some(exit_code) = self.process_info.exit_code {
+            writeln!(f, "  Exit Code: {}", exit_code)?;
+        }
+        if self.capture_count > 1 {
+            writeln!(f, "  Merged from {} captures", self.capture_count)?;
+        }
 
         writeln!(f)?;
         for thread in &self.threads {
@@ -83,7 +201,7 @@ impl std::fmt::Display for ProcessSample {
 
         writeln!(f, "Modules:")?;
         for module in &self.process_info.modules {
-            writeln!(
+            write!(
                 f,
                 "  {:#x} - {:#x}  {:24} {}",
                 module.address_range().start,
@@ -91,6 +209,10 @@ impl std::fmt::Display for ProcessSample {
                 module.name().unwrap_or("{unknown}"),
                 module.file_path().unwrap_or("")
             )?;
+            match module.host_file_path() {
+                Some(host_path) => writeln!(f, " (host: {})", host_path)?,
+                None => writeln!(f)?,
+            }
         }
 
         Ok(())
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use super::*;
+
+/// Time between scans of the process list for new descendants.
+const SCAN_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Watches for descendants of processes on a background thread, including the ones created
+/// after the watcher started, and prepares their captures, so that neither scanning the process
+/// list nor loading the symbols of a new process delays sampling.
+pub struct ProcessTreeWatcher {
+    receiver: Receiver<ProcessCapture>,
+    should_stop: Arc<AtomicBool>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl ProcessTreeWatcher {
+    /// Watch the descendants of the specified processes. The timelines of their threads are only
+    /// recorded when `keep_timelines` is set.
+    pub fn spawn(root_pids: &[Pid], keep_timelines: bool) -> Self {
+        let (sender, receiver) = channel();
+        let should_stop = Arc::new(AtomicBool::new(false));
+        let should_stop_watching = should_stop.clone();
+        let root_pids = root_pids.to_vec();
+        let watcher = std::thread::spawn(move || {
+            // A pid is only reused once its process has exited, so a process created before the one
+            // that now has its parent pid was the child of an earlier process with that pid.
+            let mut tree_processes: HashMap<Pid, u64> = root_pids
+                .into_iter()
+                .map(|pid| {
+                    (
+                        pid,
+                        process_selector::creation_time(pid).unwrap_or_default(),
+                    )
+                })
+                .collect();
+            while !should_stop_watching.load(Ordering::SeqCst) {
+                if let Some(processes) = process_iterator::ProcessIterator::snapshot() {
+                    let processes: Vec<(Pid, Pid)> = processes
+                        .map(|process| (process.pid, process.parent_pid))
+                        .collect();
+                    for (pid, parent_pid) in new_descendants(
+                        &mut tree_processes,
+                        &processes,
+                        process_selector::creation_time,
+                    ) {
+                        // Processes that can't be attached to have either exited already or aren't
+                        // accessible.
+                        let Ok(mut capture) = Sampler::attach(pid).and_then(|sampler| {
+                            ProcessCapture::start(sampler, pid, false, keep_timelines)
+                        }) else {
+                            continue;
+                        };
+                        capture.set_parent_pid(parent_pid);
+                        if sender.send(capture).is_err() {
+                            return;
+                        }
+                    }
+                }
+                std::thread::sleep(SCAN_INTERVAL);
+            }
+        });
+        Self {
+            receiver,
+            should_stop,
+            watcher: Some(watcher),
+        }
+    }
+
+    /// The captures of the descendants found since the last call, ready to be sampled.
+    pub fn new_captures(&self) -> Vec<ProcessCapture> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl Drop for ProcessTreeWatcher {
+    /// Stop watching, waiting for the capture being prepared, if any, so no capture is started
+    /// once the watcher is gone.
+    fn drop(&mut self) {
+        self.should_stop.store(true, Ordering::SeqCst);
+        if let Some(watcher) = self.watcher.take() {
+            _ = watcher.join();
+        }
+    }
+}
+
+/// Find the processes whose parent is in the tree, or becomes part of it, and add them to the tree
+/// along with their creation time. The processes are pairs of pid and parent pid, in no particular
+/// order. A process created before its parent has a parent pid that was reused, and is left out,
+/// as are the processes whose creation time can't be queried.
+fn new_descendants(
+    tree_processes: &mut HashMap<Pid, u64>,
+    processes: &[(Pid, Pid)],
+    creation_time: impl Fn(Pid) -> Option<u64>,
+) -> Vec<(Pid, Pid)> {
+    let mut descendants = Vec::new();
+    loop {
+        let count = descendants.len();
+        for &(pid, parent_pid) in processes {
+            if pid == parent_pid || tree_processes.contains_key(&pid) {
+                continue;
+            }
+            let Some(&parent_creation_time) = tree_processes.get(&parent_pid) else {
+                continue;
+            };
+            match creation_time(pid) {
+                Some(time) if time >= parent_creation_time => {
+                    tree_processes.insert(pid, time);
+                    descendants.push((pid, parent_pid));
+                }
+                _ => {}
+            }
+        }
+        if descendants.len() == count {
+            return descendants;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grandchildren_listed_before_children_found() {
+        let mut tree_processes = HashMap::from([(1, 10)]);
+        let processes = [(4, 3), (3, 1), (2, 5), (6, 1)];
+
+        assert_eq!(
+            new_descendants(&mut tree_processes, &processes, |pid| Some(10 + pid as u64)),
+            vec![(3, 1), (6, 1), (4, 3)]
+        );
+        assert_eq!(
+            new_descendants(&mut tree_processes, &processes, |pid| Some(10 + pid as u64)),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_children_of_reused_parent_pid_left_out() {
+        let mut tree_processes = HashMap::from([(1, 10)]);
+        // Process 2 outlived an earlier process 1, and process 4 can't be queried.
+        let processes = [(2, 1), (3, 1), (4, 1), (5, 2)];
+        let creation_times = HashMap::from([(2, 5), (3, 10), (5, 20)]);
+
+        assert_eq!(
+            new_descendants(&mut tree_processes, &processes, |pid| creation_times
+                .get(&pid)
+                .copied()),
+            vec![(3, 1)]
+        );
+    }
+}
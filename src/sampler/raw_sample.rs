@@ -5,6 +5,8 @@ use super::*;
 #[derive(Debug)]
 pub struct RawSample {
     thread_id: Tid,
+    tick: u64,
+    timestamp: Duration,
     user_cpu_time: Duration,
     kernel_cpu_time: Duration,
     backtrace: Vec<u64>,
@@ -13,12 +15,16 @@ pub struct RawSample {
 impl RawSample {
     pub fn new(
         thread_id: Tid,
+        tick: u64,
+        timestamp: Duration,
         user_cpu_time: Duration,
         kernel_cpu_time: Duration,
         backtrace: Vec<u64>,
     ) -> Self {
         Self {
             thread_id,
+            tick,
+            timestamp,
             user_cpu_time,
             kernel_cpu_time,
             backtrace,
@@ -29,6 +35,16 @@ impl RawSample {
         self.thread_id
     }
 
+    /// The sequence number of the snapshot this sample was taken in.
+    pub fn get_tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// The time the sample was taken, relative to the start of the capture.
+    pub fn get_timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
     pub fn get_user_cpu_time(&self) -> Duration {
         self.user_cpu_time
     }
@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct SamplePoint {
     level: u32,
     address: u64,
@@ -14,6 +14,13 @@ impl SamplePoint {
         }
     }
 
+    pub fn with_count(level: u32, address: u64, count: u32) -> Self {
+        Self {
+            count,
+            ..Self::new(level, address)
+        }
+    }
+
     pub fn root_sample() -> Self {
         Self {
             level: 0,
@@ -22,8 +29,8 @@ impl SamplePoint {
         }
     }
 
-    pub fn increment_count(&mut self) {
-        self.count += 1;
+    pub fn add_count(&mut self, count: u32) {
+        self.count += count;
     }
 
     pub fn get_level(&self) -> u32 {
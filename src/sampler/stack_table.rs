@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+pub type StackId = u32;
+
+/// Table of all distinct stacks of a capture, shared by all threads.
+///
+/// Each stack is stored as its innermost frame along with the id of the stack of its caller,
+/// so stacks with a common prefix share the frames of that prefix, and samples only need to
+/// refer to a stack by its id.
+#[derive(Debug, Default)]
+pub struct StackTable {
+    frames: Vec<StackFrame>,
+    frame_ids: HashMap<(Option<StackId>, u64), StackId>,
+}
+
+#[derive(Debug)]
+pub struct StackFrame {
+    parent: Option<StackId>,
+    address: u64,
+}
+
+impl StackTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the id of the specified backtrace, ordered from the outermost frame to the innermost one,
+    /// adding it to the table if needed. Empty backtraces don't have an id.
+    pub fn intern(&mut self, backtrace: impl IntoIterator<Item = u64>) -> Option<StackId> {
+        backtrace.into_iter().fold(None, |parent, address| {
+            Some(self.intern_frame(parent, address))
+        })
+    }
+
+    /// Get the id of the stack consisting of the specified parent stack and innermost frame,
+    /// adding it to the table if needed.
+    pub fn intern_frame(&mut self, parent: Option<StackId>, address: u64) -> StackId {
+        let next_id = self.frames.len() as StackId;
+        *self.frame_ids.entry((parent, address)).or_insert_with(|| {
+            self.frames.push(StackFrame { parent, address });
+            next_id
+        })
+    }
+
+    pub fn get_frame(&self, stack_id: StackId) -> &StackFrame {
+        &self.frames[stack_id as usize]
+    }
+
+    /// All stacks in the order they were added, where the parent of a stack always precedes it.
+    pub fn get_frames(&self) -> &[StackFrame] {
+        &self.frames
+    }
+
+    /// The backtrace of the specified stack, ordered from the outermost frame to the innermost one.
+    pub fn backtrace(&self, stack_id: Option<StackId>) -> Vec<u64> {
+        let mut backtrace = Vec::new();
+        let mut current = stack_id;
+        while let Some(stack_id) = current {
+            let frame = self.get_frame(stack_id);
+            backtrace.push(frame.address);
+            current = frame.parent;
+        }
+        backtrace.reverse();
+        backtrace
+    }
+}
+
+impl StackFrame {
+    pub fn get_parent(&self) -> Option<StackId> {
+        self.parent
+    }
+
+    pub fn get_address(&self) -> u64 {
+        self.address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_common_prefix_shared() {
+        let mut stack_table = StackTable::new();
+
+        let first = stack_table.intern([1, 2, 3]);
+        let second = stack_table.intern([1, 2, 4]);
+
+        assert_eq!(stack_table.intern([1, 2, 3]), first);
+        assert_eq!(stack_table.intern([]), None);
+        assert_eq!(stack_table.get_frames().len(), 4);
+        assert_eq!(stack_table.backtrace(first), vec![1, 2, 3]);
+        assert_eq!(stack_table.backtrace(second), vec![1, 2, 4]);
+        assert_eq!(stack_table.backtrace(None), Vec::<u64>::new());
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use windows::core::GUID;
+
+use super::*;
+
+/// Maximum total size of the cache files, beyond which the least recently used modules are evicted.
+const MAX_CACHE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Maximum number of functions cached for a single module.
+const MAX_FUNCTIONS_PER_MODULE: usize = 100_000;
+
+/// "PE\0\0"
+const NT_SIGNATURE: u32 = 0x0000_4550;
+
+/// "RSDS", the signature of CodeView records that refer to a PDB file.
+const RSDS_SIGNATURE: u32 = 0x5344_5352;
+
+/// Identity of a specific build of a module, taken from its image headers.
+#[derive(Debug)]
+pub struct ModuleIdentity {
+    name: String,
+    timestamp: u32,
+    image_size: u32,
+    /// GUID and age of the PDB file of the module, when it has one.
+    pdb: Option<(GUID, u32)>,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CodeViewRecord {
+    signature: u32,
+    guid: GUID,
+    age: u32,
+}
+
+impl ModuleIdentity {
+    /// Read the identity of the specified module from the memory of the sampled process.
+    pub fn read(process_handle: HANDLE, module: &ModuleInfo) -> Option<Self> {
+        let base_address = module.address_range().start;
+        unsafe {
+            let nt_headers_offset: u32 = read_process_memory(process_handle, base_address + 0x3c)?;
+            let nt_headers: IMAGE_NT_HEADERS64 =
+                read_process_memory(process_handle, base_address + nt_headers_offset as u64)?;
+            // The optional header of 32-bit images is laid out differently.
+            if nt_headers.Signature != NT_SIGNATURE
+                || nt_headers.OptionalHeader.Magic != IMAGE_NT_OPTIONAL_HDR64_MAGIC
+            {
+                return None;
+            }
+
+            let debug_directory =
+                nt_headers.OptionalHeader.DataDirectory[IMAGE_DIRECTORY_ENTRY_DEBUG.0 as usize];
+            let entry_count = debug_directory.Size as usize / size_of::<IMAGE_DEBUG_DIRECTORY>();
+            let pdb = (0..entry_count)
+                .filter_map(|index| {
+                    read_process_memory::<IMAGE_DEBUG_DIRECTORY>(
+                        process_handle,
+                        base_address
+                            + debug_directory.VirtualAddress as u64
+                            + (index * size_of::<IMAGE_DEBUG_DIRECTORY>()) as u64,
+                    )
+                })
+                .filter(|entry| entry.Type == IMAGE_DEBUG_TYPE_CODEVIEW)
+                .filter_map(|entry| {
+                    read_process_memory::<CodeViewRecord>(
+                        process_handle,
+                        base_address + entry.AddressOfRawData as u64,
+                    )
+                })
+                .find(|record| record.signature == RSDS_SIGNATURE)
+                .map(|record| (record.guid, record.age));
+
+            Some(Self {
+                name: module.name()?.to_string(),
+                timestamp: nt_headers.FileHeader.TimeDateStamp,
+                image_size: nt_headers.OptionalHeader.SizeOfImage,
+                pdb,
+            })
+        }
+    }
+
+    /// Name of the cache file of the module, following the layout of symbol server keys.
+    fn file_name(&self) -> String {
+        let mut file_name = format!("{}-{:08X}{:X}", self.name, self.timestamp, self.image_size);
+        if let Some((guid, age)) = &self.pdb {
+            file_name += &format!(
+                "-{:08X}{:04X}{:04X}{}{:X}",
+                guid.data1,
+                guid.data2,
+                guid.data3,
+                guid.data4
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect::<String>(),
+                age
+            );
+        }
+        file_name + ".json"
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CachedModule {
+    module: Option<String>,
+    functions: Vec<CachedFunction>,
+}
+
+/// A function of a module, where `start` and `end` are offsets from the base address of the module.
+#[derive(Serialize, Deserialize, Debug)]
+struct CachedFunction {
+    start: u64,
+    end: u64,
+    function: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    line: Option<u32>,
+}
+
+/// On-disk cache of the function ranges of modules, shared by all captures, so modules that were
+/// symbolicated by a previous capture don't need to be symbolicated again.
+///
+/// Each build of a module has its own cache file, and the least recently used files are evicted
+/// once the cache exceeds its size limit.
+pub struct SymbolCache {
+    directory: PathBuf,
+    /// Maximum total size of the cache files.
+    max_size: u64,
+    /// Number of functions loaded from each cache file, to only rewrite the ones that changed.
+    loaded_function_counts: HashMap<String, usize>,
+}
+
+impl SymbolCache {
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            max_size: MAX_CACHE_SIZE,
+            loaded_function_counts: HashMap::new(),
+        }
+    }
+
+    /// The cache in the local application data directory of the user.
+    pub fn open_default() -> Option<Self> {
+        let local_app_data = std::env::var_os("LOCALAPPDATA")?;
+        Some(Self::new(
+            Path::new(&local_app_data)
+                .join("spinsample")
+                .join("symbols"),
+        ))
+    }
+
+    /// Add the cached functions of the specified module to the symbol table.
+    pub fn load(
+        &mut self,
+        identity: &ModuleIdentity,
+        module_range: Range<u64>,
+        symbol_table: &mut SymbolTable,
+    ) {
+        let file_name = identity.file_name();
+        let file_path = self.directory.join(&file_name);
+        let Ok(cached_module) = fs::read(&file_path)
+            .map_err(|_| ())
+            .and_then(|data| serde_json::from_slice::<CachedModule>(&data).map_err(|_| ()))
+        else {
+            return;
+        };
+
+        // Mark the file as recently used.
+        if let Ok(file) = fs::File::options().append(true).open(&file_path) {
+            _ = file.set_modified(SystemTime::now());
+        }
+
+        let module = cached_module.module.map(PathBuf::from);
+        for function in &cached_module.functions {
+            symbol_table.insert_function(
+                module_range.clone(),
+                module_range.start + function.start..module_range.start + function.end,
+                SymbolInfo::new(Some(function.function.clone()), module.clone())
+                    .with_source(function.file.as_ref().map(PathBuf::from), function.line),
+            );
+        }
+        self.loaded_function_counts
+            .insert(file_name, cached_module.functions.len());
+    }
+
+    /// Write the functions of the specified module that are in the symbol table to the cache,
+    /// unless no function was added since it was loaded.
+    pub fn store(
+        &self,
+        identity: &ModuleIdentity,
+        module_range: Range<u64>,
+        symbol_table: &SymbolTable,
+    ) -> io::Result<()> {
+        let file_name = identity.file_name();
+        let functions = symbol_table.module_functions(module_range.start);
+        if functions.is_empty()
+            || self.loaded_function_counts.get(&file_name) == Some(&functions.len())
+        {
+            return Ok(());
+        }
+
+        let cached_module = CachedModule {
+            module: functions
+                .iter()
+                .find_map(|(_, symbol)| symbol.get_module())
+                .map(|module| module.to_string_lossy().into_owned()),
+            functions: functions
+                .iter()
+                .filter_map(|(range, symbol)| {
+                    Some(CachedFunction {
+                        start: range.start - module_range.start,
+                        end: range.end - module_range.start,
+                        function: symbol.get_function()?.to_string(),
+                        file: symbol
+                            .get_file()
+                            .map(|file| file.to_string_lossy().into_owned()),
+                        line: symbol.get_line(),
+                    })
+                })
+                .take(MAX_FUNCTIONS_PER_MODULE)
+                .collect(),
+        };
+
+        // Write to a file of this process first, so other processes never read a partial file.
+        fs::create_dir_all(&self.directory)?;
+        let temp_path = self
+            .directory
+            .join(format!("{}.{}.tmp", file_name, std::process::id()));
+        fs::write(
+            &temp_path,
+            serde_json::to_vec(&cached_module).map_err(io::Error::other)?,
+        )?;
+        if let Err(error) = fs::rename(&temp_path, self.directory.join(&file_name)) {
+            _ = fs::remove_file(&temp_path);
+            return Err(error);
+        }
+        self.evict()
+    }
+
+    /// Remove the least recently used cache files until the cache fits its size limit.
+    fn evict(&self) -> io::Result<()> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            // Leave out the files other processes are still writing.
+            if entry
+                .path()
+                .extension()
+                .is_none_or(|extension| extension != "json")
+            {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            files.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+        files.sort();
+
+        let mut cache_size: u64 = files.iter().map(|(_, size, _)| size).sum();
+        for (_, size, path) in files {
+            if cache_size <= self.max_size {
+                break;
+            }
+            fs::remove_file(path)?;
+            cache_size -= size;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_functions_restored_at_module_base() {
+        let identity = ModuleIdentity {
+            name: "app.exe".to_string(),
+            timestamp: 0x12345678,
+            image_size: 0x10000,
+            pdb: Some((GUID::from_u128(0x0123456789abcdef0123456789abcdef), 2)),
+        };
+        assert_eq!(
+            identity.file_name(),
+            "app.exe-1234567810000-0123456789ABCDEF0123456789ABCDEF2.json"
+        );
+
+        let directory = std::env::temp_dir().join(format!(
+            "spinsample-symbol-cache-test-{}",
+            std::process::id()
+        ));
+        let mut symbol_table = SymbolTable::new();
+        symbol_table.insert_function(
+            0x1000..0x11000,
+            0x1100..0x1180,
+            SymbolInfo::new(Some("main".to_string()), Some(PathBuf::from("app")))
+                .with_source(Some(PathBuf::from("main.rs")), Some(12)),
+        );
+        SymbolCache::new(directory.clone())
+            .store(&identity, 0x1000..0x11000, &symbol_table)
+            .unwrap();
+
+        let mut symbol_cache = SymbolCache::new(directory.clone());
+        let mut loaded_symbol_table = SymbolTable::new();
+        symbol_cache.load(&identity, 0x5000..0x15000, &mut loaded_symbol_table);
+        _ = fs::remove_dir_all(&directory);
+
+        let functions = loaded_symbol_table.module_functions(0x5000);
+        assert_eq!(functions.len(), 1);
+        let (range, symbol) = &functions[0];
+        assert_eq!(*range, 0x5100..0x5180);
+        assert_eq!(symbol.get_function(), Some("main"));
+        assert_eq!(symbol.get_module_name(), Some("app"));
+        assert_eq!(symbol.get_file(), Some(Path::new("main.rs")));
+        assert_eq!(symbol.get_line(), Some(12));
+    }
+
+    #[test]
+    fn test_least_recently_used_files_evicted() {
+        let directory = std::env::temp_dir().join(format!(
+            "spinsample-symbol-cache-evict-test-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&directory).unwrap();
+        let now = SystemTime::now();
+        for (file_name, age) in [("oldest.json", 30), ("recent.json", 10), ("older.json", 20)] {
+            let file = fs::File::create(directory.join(file_name)).unwrap();
+            file.set_len(100).unwrap();
+            file.set_modified(now - std::time::Duration::from_secs(age))
+                .unwrap();
+        }
+
+        let symbol_cache = SymbolCache {
+            max_size: 200,
+            ..SymbolCache::new(directory.clone())
+        };
+        symbol_cache.evict().unwrap();
+        let mut remaining: Vec<String> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        _ = fs::remove_dir_all(&directory);
+
+        remaining.sort();
+        assert_eq!(remaining, vec!["older.json", "recent.json"]);
+    }
+}
@@ -1,50 +1,189 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
 use std::path::PathBuf;
 
+use super::symbolicator::SymbolicatedFrame;
 use super::*;
 
 #[derive(Debug)]
 pub struct SymbolTable {
     address_to_symbol_table: std::collections::HashMap<u64, SymbolInfo>,
+    function_ranges: FunctionRangeCache,
 }
 
 #[derive(Debug, Clone)]
 pub struct SymbolInfo {
     function: Option<String>,
     module: Option<PathBuf>,
+    file: Option<PathBuf>,
+    line: Option<u32>,
 }
 
 impl SymbolTable {
     pub fn new() -> Self {
         Self {
             address_to_symbol_table: std::collections::HashMap::new(),
+            function_ranges: FunctionRangeCache::default(),
         }
     }
 
-    pub fn symbolicate(&mut self, backtrace: &Vec<u64>, symbolicator: &Symbolicator) {
+    pub fn symbolicate(&mut self, backtrace: &[u64], symbolicator: &Symbolicator) {
+        self.symbolicate_with(backtrace, |address| symbolicator.symbolicate(address));
+    }
+
+    /// Symbolicate the addresses that aren't in the table yet, only calling `symbolicate` for
+    /// addresses outside of the functions resolved so far.
+    fn symbolicate_with(
+        &mut self,
+        backtrace: &[u64],
+        mut symbolicate: impl FnMut(u64) -> SymbolicatedFrame,
+    ) {
         for address in backtrace {
-            if !self.address_to_symbol_table.contains_key(address) {
-                let symbolicated_frame = symbolicator.symbolicate(*address);
-                self.address_to_symbol_table.insert(
-                    *address,
-                    SymbolInfo {
+            if self.address_to_symbol_table.contains_key(address) {
+                continue;
+            }
+
+            let symbol = match self.function_ranges.symbol(*address) {
+                Some(symbol) => symbol.clone(),
+                None => {
+                    let symbolicated_frame = symbolicate(*address);
+                    let symbol = SymbolInfo {
                         function: symbolicated_frame.function,
                         module: symbolicated_frame.module.map(PathBuf::from),
-                    },
-                );
-            }
+                        file: symbolicated_frame.file.map(PathBuf::from),
+                        line: symbolicated_frame.line,
+                    };
+                    if let (Some(module_range), Some(function_range)) = (
+                        symbolicated_frame.module_range,
+                        symbolicated_frame.function_range,
+                    ) {
+                        self.function_ranges
+                            .insert(module_range, function_range, symbol.clone());
+                    }
+                    symbol
+                }
+            };
+            self.address_to_symbol_table.insert(*address, symbol);
         }
     }
 
     pub fn symbol(&self, address: u64) -> Option<&SymbolInfo> {
         self.address_to_symbol_table.get(&address)
     }
+
+    pub fn insert(&mut self, address: u64, symbol: SymbolInfo) {
+        self.address_to_symbol_table.insert(address, symbol);
+    }
+
+    /// Add a function whose addresses are symbolicated without the symbolicator.
+    pub(super) fn insert_function(
+        &mut self,
+        module_range: Range<u64>,
+        function_range: Range<u64>,
+        symbol: SymbolInfo,
+    ) {
+        self.function_ranges
+            .insert(module_range, function_range, symbol);
+    }
+
+    /// The functions resolved so far within the module at the specified base address, ordered by address.
+    pub(super) fn module_functions(&self, module_base: u64) -> Vec<(Range<u64>, &SymbolInfo)> {
+        self.function_ranges
+            .modules
+            .get(&module_base)
+            .map(|module| {
+                module
+                    .functions
+                    .iter()
+                    .map(|(start, (end, symbol))| (*start..*end, symbol))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Iterate over all symbolicated addresses, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &SymbolInfo)> {
+        self.address_to_symbol_table
+            .iter()
+            .map(|(address, symbol)| (*address, symbol))
+    }
+}
+
+/// Address ranges of the functions resolved so far, grouped by module, so that other addresses
+/// within the same functions are symbolicated without going back to DbgHelp.
+#[derive(Debug, Default)]
+struct FunctionRangeCache {
+    /// Functions of each module, by module base address.
+    modules: BTreeMap<u64, ModuleFunctions>,
+}
+
+#[derive(Debug)]
+struct ModuleFunctions {
+    end: u64,
+    /// End address and symbol of each function, by start address.
+    functions: BTreeMap<u64, (u64, SymbolInfo)>,
+}
+
+impl FunctionRangeCache {
+    fn symbol(&self, address: u64) -> Option<&SymbolInfo> {
+        let (_, module) = self
+            .modules
+            .range(..=address)
+            .next_back()
+            .filter(|(_, module)| address < module.end)?;
+        let (_, (_, symbol)) = module
+            .functions
+            .range(..=address)
+            .next_back()
+            .filter(|(_, (end, _))| address < *end)?;
+        Some(symbol)
+    }
+
+    fn insert(&mut self, module_range: Range<u64>, function_range: Range<u64>, symbol: SymbolInfo) {
+        self.modules
+            .entry(module_range.start)
+            .or_insert_with(|| ModuleFunctions {
+                end: module_range.end,
+                functions: BTreeMap::new(),
+            })
+            .functions
+            .insert(function_range.start, (function_range.end, symbol));
+    }
 }
 
 impl SymbolInfo {
+    pub fn new(function: Option<String>, module: Option<PathBuf>) -> Self {
+        Self {
+            function,
+            module,
+            file: None,
+            line: None,
+        }
+    }
+
+    /// Set the source file and line where the function starts.
+    pub fn with_source(mut self, file: Option<PathBuf>, line: Option<u32>) -> Self {
+        self.file = file;
+        self.line = line;
+        self
+    }
+
     pub fn get_function(&self) -> Option<&str> {
         self.function.as_ref().map(String::as_str)
     }
 
+    pub fn get_module(&self) -> Option<&std::path::Path> {
+        self.module.as_deref()
+    }
+
+    pub fn get_file(&self) -> Option<&std::path::Path> {
+        self.file.as_deref()
+    }
+
+    pub fn get_line(&self) -> Option<u32> {
+        self.line
+    }
+
     pub fn get_module_name(&self) -> Option<&str> {
         self.module
             .as_ref()
@@ -52,3 +191,41 @@ impl SymbolInfo {
             .flatten()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbolicated_frame(address: u64) -> SymbolicatedFrame {
+        // Functions of 0x10 bytes at every 0x10 address of a module at 0x1000.
+        let start = address & !0xf;
+        SymbolicatedFrame {
+            function: Some(format!("function_{:x}", start)),
+            module: Some("module".to_string()),
+            function_range: Some(start..start + 0x10),
+            module_range: Some(0x1000..0x2000),
+            file: None,
+            line: None,
+        }
+    }
+
+    #[test]
+    fn test_addresses_within_known_function_not_resolved_again() {
+        let mut symbol_table = SymbolTable::new();
+        let mut resolved_addresses = Vec::new();
+        symbol_table.symbolicate_with(&[0x1004, 0x1008, 0x100f, 0x1010, 0x1004], |address| {
+            resolved_addresses.push(address);
+            symbolicated_frame(address)
+        });
+
+        assert_eq!(resolved_addresses, vec![0x1004, 0x1010]);
+        assert_eq!(
+            symbol_table.symbol(0x100f).unwrap().get_function(),
+            Some("function_1000")
+        );
+        assert_eq!(
+            symbol_table.symbol(0x1010).unwrap().get_function(),
+            Some("function_1010")
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::JoinHandle;
+
+use super::*;
+
+/// Symbolicates addresses on a background thread while the process is being sampled,
+/// so the symbol table is ready shortly after the capture ends.
+pub struct SymbolicationWorker {
+    sender: Sender<Vec<u64>>,
+    worker: JoinHandle<(SymbolTable, Symbolicator)>,
+}
+
+impl SymbolicationWorker {
+    /// Symbolicate addresses into the specified symbol table, skipping the addresses it already covers.
+    /// The functions of each module are loaded from the symbol cache when the first address in the
+    /// module is queued, and stored back to it once all addresses are symbolicated.
+    pub fn spawn(
+        symbolicator: Symbolicator,
+        mut symbol_table: SymbolTable,
+        symbol_cache: Option<SymbolCache>,
+    ) -> Self {
+        let (sender, receiver) = channel::<Vec<u64>>();
+        let worker = std::thread::spawn(move || {
+            // Leave the CPU to the sampling thread whenever it is ready to take a snapshot.
+            unsafe {
+                _ = SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_BELOW_NORMAL);
+            }
+
+            let mut cached_modules = symbol_cache.map(CachedModules::new);
+            for addresses in receiver {
+                if let Some(cached_modules) = cached_modules.as_mut() {
+                    cached_modules.load(&addresses, &symbolicator, &mut symbol_table);
+                }
+                symbol_table.symbolicate(&addresses, &symbolicator);
+            }
+            if let Some(cached_modules) = &cached_modules {
+                cached_modules.store(&symbol_table);
+            }
+            (symbol_table, symbolicator)
+        });
+        Self { sender, worker }
+    }
+
+    /// Queue the specified addresses for symbolication. Addresses that were already symbolicated
+    /// are skipped by the worker.
+    pub fn symbolicate(&self, addresses: Vec<u64>) {
+        if !addresses.is_empty() {
+            _ = self.sender.send(addresses);
+        }
+    }
+
+    /// Wait for the worker to symbolicate all queued addresses, and hand the symbolicator back.
+    pub fn finish(self) -> (SymbolTable, Symbolicator) {
+        drop(self.sender);
+        self.worker
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    }
+}
+
+/// Modules seen by the worker, with the identity of their build in the symbol cache.
+struct CachedModules {
+    symbol_cache: SymbolCache,
+    /// Address range and identity of each module, by base address. The identity is read when the
+    /// module is first seen, as the module may be unloaded by the end of the capture.
+    modules: BTreeMap<u64, (Range<u64>, Option<ModuleIdentity>)>,
+}
+
+impl CachedModules {
+    fn new(symbol_cache: SymbolCache) -> Self {
+        Self {
+            symbol_cache,
+            modules: BTreeMap::new(),
+        }
+    }
+
+    /// Load the cached functions of the modules of the addresses that weren't seen yet.
+    fn load(
+        &mut self,
+        addresses: &[u64],
+        symbolicator: &Symbolicator,
+        symbol_table: &mut SymbolTable,
+    ) {
+        for &address in addresses {
+            let is_known = self
+                .modules
+                .range(..=address)
+                .next_back()
+                .is_some_and(|(_, (range, _))| range.contains(&address));
+            if is_known {
+                continue;
+            }
+            let Some(module) = symbolicator.module(address) else {
+                continue;
+            };
+
+            let identity = ModuleIdentity::read(symbolicator.get_process_handle(), &module);
+            if let Some(identity) = &identity {
+                self.symbol_cache
+                    .load(identity, module.address_range(), symbol_table);
+            }
+            self.modules.insert(
+                module.address_range().start,
+                (module.address_range(), identity),
+            );
+        }
+    }
+
+    /// Write the functions resolved in each module back to the cache.
+    fn store(&self, symbol_table: &SymbolTable) {
+        for (range, identity) in self.modules.values() {
+            let Some(identity) = identity else {
+                continue;
+            };
+            if let Err(error) = self
+                .symbol_cache
+                .store(identity, range.clone(), symbol_table)
+            {
+                eprintln!("Unable to update the symbol cache - {}", error);
+                break;
+            }
+        }
+    }
+}
@@ -1,69 +1,337 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::ops::Range;
+use std::time::Instant;
+
+use windows::Win32::System::Memory::*;
+
 use super::*;
 
+/// Module name of the frames in executable memory allocated by the process itself, such as JIT
+/// compiled code, which isn't part of any module. Their functions are named by the JIT symbol map
+/// of the process, when it has one.
+const PRIVATE_EXECUTABLE_MEMORY: &str = "[private executable memory]";
+
+/// Minimum time between refreshes of the module list, for addresses outside of all known modules.
+const MODULE_REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
 pub struct Symbolicator {
-    process_handle: HANDLE,
+    /// Handle of its own, which stays valid until `SymCleanup` is done with it even when the
+    /// sampler closes its handle first.
+    process_handle: Owned<HANDLE>,
+    last_module_refresh: Cell<Option<Instant>>,
+    jit_symbol_map: RefCell<JitSymbolMap>,
+    /// Base addresses of the modules whose image file was checked by `reload_moved_modules`.
+    checked_modules: RefCell<HashSet<u64>>,
 }
 
+// The symbolicator owns its process handle, and DbgHelp calls are serialized by
+// `dbghelp_lock`, so the symbolicator can be moved to the symbolication thread.
+unsafe impl Send for Symbolicator {}
+
 pub struct SymbolicatedFrame {
     pub function: Option<String>,
     pub module: Option<String>,
+    /// Addresses of the function, when its size is known.
+    pub function_range: Option<Range<u64>>,
+    /// Addresses of the module image.
+    pub module_range: Option<Range<u64>>,
+    /// Source file and line where the function starts.
+    pub file: Option<String>,
+    pub line: Option<u32>,
 }
 
 impl Symbolicator {
-    pub fn new(process_handle: HANDLE, search_path: &[&str]) -> Result<Self, Error> {
+    /// Initialize symbolication for the specified process, loading the modules it has loaded so far
+    /// when `invade_process` is set. Modules loaded later are picked up as their addresses are seen.
+    pub fn new(
+        process_handle: HANDLE,
+        search_path: &[&str],
+        invade_process: bool,
+    ) -> Result<Self, Error> {
+        let process_handle = unsafe {
+            let mut duplicate_handle = HANDLE::default();
+            DuplicateHandle(
+                GetCurrentProcess(),
+                process_handle,
+                GetCurrentProcess(),
+                &mut duplicate_handle,
+                0,
+                false,
+                DUPLICATE_SAME_ACCESS,
+            )
+            .map_err(Error::SymInitializeFailed)?;
+            Owned::new(duplicate_handle)
+        };
+
+        let _dbghelp_lock = dbghelp_lock();
         unsafe {
             let mut path_string: Vec<u16> = search_path.join(";").encode_utf16().collect();
             path_string.push(0);
-            SymInitializeW(process_handle, PCWSTR::from_raw(path_string.as_ptr()), true)
-                .map_err(|e| Error::SymInitializeFailed(e))?
+            SymInitializeW(
+                *process_handle,
+                PCWSTR::from_raw(path_string.as_ptr()),
+                invade_process,
+            )
+            .map_err(|e| Error::SymInitializeFailed(e))?
         };
-        Ok(Self { process_handle })
+        let pid = unsafe { GetProcessId(*process_handle) };
+        let symbolicator = Self {
+            process_handle,
+            last_module_refresh: Cell::new(None),
+            jit_symbol_map: RefCell::new(JitSymbolMap::for_process(pid)),
+            checked_modules: RefCell::new(HashSet::new()),
+        };
+        symbolicator.reload_moved_modules();
+        Ok(symbolicator)
     }
 
     pub fn symbolicate(&self, address: u64) -> SymbolicatedFrame {
-        let function = unsafe {
+        let _dbghelp_lock = dbghelp_lock();
+        if unsafe { self.module_info(address) }.is_none() {
+            self.refresh_modules();
+        }
+
+        let (mut function, mut function_range) = unsafe {
             let mut displacement: u64 = 0;
             let mut symbol_info = SYMBOL_INFO_PACKAGEW::default();
             symbol_info.si.SizeOfStruct = size_of::<SYMBOL_INFOW>() as u32;
             symbol_info.si.MaxNameLen = MAX_SYM_NAME;
 
             if SymFromAddrW(
-                self.process_handle,
+                *self.process_handle,
                 address,
                 Some(&mut displacement),
                 &mut symbol_info.si,
             )
             .is_ok()
             {
-                PCWSTR::from_raw(symbol_info.si.Name.as_ptr())
+                let function = PCWSTR::from_raw(symbol_info.si.Name.as_ptr())
                     .to_string()
-                    .ok()
+                    .ok();
+                let function_range =
+                    symbol_info.si.Address..symbol_info.si.Address + symbol_info.si.Size as u64;
+                (
+                    function,
+                    function_range.contains(&address).then_some(function_range),
+                )
             } else {
-                None
+                (None, None)
             }
         };
 
-        let module = unsafe {
-            let mut module_info = IMAGEHLP_MODULEW64::default();
-            module_info.SizeOfStruct = size_of::<IMAGEHLP_MODULEW64>() as u32;
-
-            if SymGetModuleInfoW64(self.process_handle, address, &mut module_info).is_ok() {
-                PCWSTR::from_raw(module_info.ModuleName.as_ptr())
+        let (module, module_range) = unsafe {
+            if let Some(module_info) = self.module_info(address) {
+                let module = PCWSTR::from_raw(module_info.ModuleName.as_ptr())
                     .to_string()
-                    .ok()
+                    .ok();
+                let module_range =
+                    module_info.BaseOfImage..module_info.BaseOfImage + module_info.ImageSize as u64;
+                (module, Some(module_range))
+            } else if self.is_private_executable(address) {
+                if function.is_none() {
+                    if let Some((range, name)) = self.jit_symbol_map.borrow_mut().function(address)
+                    {
+                        function = Some(name.to_string());
+                        function_range = Some(range);
+                    }
+                }
+                (Some(PRIVATE_EXECUTABLE_MEMORY.to_string()), None)
             } else {
-                None
+                (None, None)
             }
         };
 
-        SymbolicatedFrame { function, module }
+        let (file, line) = function_range
+            .as_ref()
+            .and_then(|function_range| unsafe {
+                let mut displacement: u32 = 0;
+                let mut line_info = IMAGEHLP_LINEW64::default();
+                line_info.SizeOfStruct = size_of::<IMAGEHLP_LINEW64>() as u32;
+
+                SymGetLineFromAddrW64(
+                    *self.process_handle,
+                    function_range.start,
+                    &mut displacement,
+                    &mut line_info,
+                )
+                .ok()?;
+                Some((
+                    line_info.FileName.to_string().ok(),
+                    Some(line_info.LineNumber),
+                ))
+            })
+            .unwrap_or_default();
+
+        SymbolicatedFrame {
+            function,
+            module,
+            function_range,
+            module_range,
+            file,
+            line,
+        }
+    }
+
+    /// Whether the address is in executable memory that the process allocated rather than mapped
+    /// from an image or a file.
+    unsafe fn is_private_executable(&self, address: u64) -> bool {
+        let mut memory_info = MEMORY_BASIC_INFORMATION::default();
+        let executable =
+            PAGE_EXECUTE | PAGE_EXECUTE_READ | PAGE_EXECUTE_READWRITE | PAGE_EXECUTE_WRITECOPY;
+        VirtualQueryEx(
+            *self.process_handle,
+            Some(address as *const c_void),
+            &mut memory_info,
+            size_of::<MEMORY_BASIC_INFORMATION>(),
+        ) != 0
+            && memory_info.Type == MEM_PRIVATE
+            && (memory_info.Protect & executable).0 != 0
+    }
+
+    pub fn get_process_handle(&self) -> HANDLE {
+        *self.process_handle
+    }
+
+    /// The module containing the address, picking up the modules loaded since the last refresh
+    /// when it isn't known yet.
+    pub fn module(&self, address: u64) -> Option<ModuleInfo> {
+        let _dbghelp_lock = dbghelp_lock();
+        unsafe {
+            let module_info = self.module_info(address).or_else(|| {
+                self.refresh_modules();
+                self.module_info(address)
+            })?;
+            module_from_info(&module_info)
+        }
+    }
+
+    /// The modules known to the symbolicator, including the ones that were loaded after it was
+    /// initialized and have since been seen, even when the process has exited.
+    pub fn loaded_modules(&self) -> Vec<ModuleInfo> {
+        let _dbghelp_lock = dbghelp_lock();
+        self.refresh_modules();
+        unsafe {
+            self.module_bases()
+                .into_iter()
+                .filter_map(|base_address| module_from_info(&self.module_info(base_address)?))
+                .collect()
+        }
+    }
+
+    /// Pick up the modules loaded since the last refresh, at most once per refresh interval.
+    /// Expects the DbgHelp lock to be held.
+    fn refresh_modules(&self) {
+        if self
+            .last_module_refresh
+            .get()
+            .is_some_and(|last_refresh| last_refresh.elapsed() < MODULE_REFRESH_INTERVAL)
+        {
+            return;
+        }
+        unsafe {
+            _ = SymRefreshModuleList(*self.process_handle);
+        }
+        self.reload_moved_modules();
+        self.last_module_refresh.set(Some(Instant::now()));
+    }
+
+    /// Load the modules whose image file isn't at the path the process reports from their host
+    /// path, such as the modules of processes in containers, or of an in-place upgrade that renamed
+    /// them, where the reported path would lead to another build or to no file at all.
+    /// Expects the DbgHelp lock to be held.
+    fn reload_moved_modules(&self) {
+        let mut checked_modules = self.checked_modules.borrow_mut();
+        let mut dos_devices = None;
+        unsafe {
+            for base_address in self.module_bases() {
+                if !checked_modules.insert(base_address) {
+                    continue;
+                }
+                let Some(module_info) = self.module_info(base_address) else {
+                    continue;
+                };
+                let dos_devices = dos_devices.get_or_insert_with(DosDevices::query);
+                let image_path = PCWSTR::from_raw(module_info.ImageName.as_ptr())
+                    .to_string()
+                    .unwrap_or_default();
+                let Some(host_path) = mapped_image::host_path(
+                    *self.process_handle,
+                    base_address,
+                    &image_path,
+                    dos_devices,
+                ) else {
+                    continue;
+                };
+
+                let host_path: Vec<u16> = host_path
+                    .to_str()
+                    .unwrap_or_default()
+                    .encode_utf16()
+                    .chain([0])
+                    .collect();
+                _ = SymUnloadModule64(*self.process_handle, base_address);
+                // Keep the module name, so frames are attributed to the module the process loaded.
+                SymLoadModuleExW(
+                    *self.process_handle,
+                    None,
+                    PCWSTR::from_raw(host_path.as_ptr()),
+                    PCWSTR::from_raw(module_info.ModuleName.as_ptr()),
+                    base_address,
+                    module_info.ImageSize,
+                    None,
+                    None,
+                );
+            }
+        }
+    }
+
+    /// Base addresses of the modules loaded in DbgHelp. Expects the DbgHelp lock to be held.
+    unsafe fn module_bases(&self) -> Vec<u64> {
+        extern "system" fn callback(
+            _module_name: PCWSTR,
+            base_address: u64,
+            base_addresses_ptr: *const c_void,
+        ) -> BOOL {
+            let base_addresses = unsafe { &mut *(base_addresses_ptr as *mut Vec<u64>) };
+            base_addresses.push(base_address);
+            BOOL::from(true)
+        }
+
+        let mut base_addresses = Vec::<u64>::new();
+        _ = SymEnumerateModulesW64(
+            *self.process_handle,
+            Some(callback),
+            Some(&raw mut base_addresses as *const c_void),
+        );
+        base_addresses
+    }
+
+    /// Expects the DbgHelp lock to be held.
+    unsafe fn module_info(&self, address: u64) -> Option<IMAGEHLP_MODULEW64> {
+        let mut module_info = IMAGEHLP_MODULEW64::default();
+        module_info.SizeOfStruct = size_of::<IMAGEHLP_MODULEW64>() as u32;
+        SymGetModuleInfoW64(*self.process_handle, address, &mut module_info).ok()?;
+        Some(module_info)
     }
 }
 
+unsafe fn module_from_info(module_info: &IMAGEHLP_MODULEW64) -> Option<ModuleInfo> {
+    let path = PCWSTR::from_raw(module_info.ImageName.as_ptr())
+        .to_string()
+        .ok()?;
+    Some(ModuleInfo::new(
+        PathBuf::from(path),
+        module_info.BaseOfImage,
+        module_info.ImageSize,
+    ))
+}
+
 impl Drop for Symbolicator {
     fn drop(&mut self) {
+        let _dbghelp_lock = dbghelp_lock();
         unsafe {
-            _ = SymCleanup(self.process_handle);
+            _ = SymCleanup(*self.process_handle);
         }
     }
 }
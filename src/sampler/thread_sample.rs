@@ -1,14 +1,48 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
 use std::time::Duration;
-use tree_iterators_rs::prelude::*;
 
 use super::*;
 
-#[derive(Debug)]
+/// Index of the root node within the nodes of a sample tree.
+const ROOT: usize = 0;
+
+/// Nodes with up to this many children are searched linearly, which is faster than hashing
+/// for the narrow nodes that make up most of a typical sample tree.
+const MAX_LINEAR_CHILDREN: usize = 8;
+
+#[derive(Debug, PartialEq)]
 pub struct ThreadSample {
     thread_id: Tid,
     user_cpu_time: Duration,
     kernel_cpu_time: Duration,
-    sample_tree: TreeNode<SamplePoint>,
+    /// The nodes of the sample tree, where the root node is always the first one.
+    nodes: Vec<SampleNode>,
+    /// The index of each child node by the index of its parent node and its address,
+    /// for the nodes with more than `MAX_LINEAR_CHILDREN` children.
+    child_nodes: HashMap<(usize, u64), usize, BuildHasherDefault<ChildHasher>>,
+}
+
+#[derive(Debug, PartialEq)]
+struct SampleNode {
+    value: SamplePoint,
+    child_count: usize,
+    /// The children of a node form a list in the order they were added.
+    first_child: Option<usize>,
+    last_child: Option<usize>,
+    next_sibling: Option<usize>,
+}
+
+impl SampleNode {
+    fn new(value: SamplePoint) -> Self {
+        Self {
+            value,
+            child_count: 0,
+            first_child: None,
+            last_child: None,
+            next_sibling: None,
+        }
+    }
 }
 
 impl ThreadSample {
@@ -17,10 +51,8 @@ impl ThreadSample {
             thread_id,
             user_cpu_time,
             kernel_cpu_time,
-            sample_tree: TreeNode {
-                value: SamplePoint::root_sample(),
-                children: Vec::new(),
-            },
+            nodes: vec![SampleNode::new(SamplePoint::root_sample())],
+            child_nodes: HashMap::default(),
         }
     }
 
@@ -36,58 +68,206 @@ impl ThreadSample {
         self.kernel_cpu_time
     }
 
+    pub fn set_cpu_time(&mut self, user_cpu_time: Duration, kernel_cpu_time: Duration) {
+        self.user_cpu_time = user_cpu_time;
+        self.kernel_cpu_time = kernel_cpu_time;
+    }
+
+    /// The total number of backtraces added to the sample tree.
+    pub fn get_sample_count(&self) -> u32 {
+        self.nodes[ROOT].value.get_count()
+    }
+
     /// Generate sample tree representing the specified backtrace,
     /// and combine with the existing tree by incrementing the counter for common nodes.
     pub fn add_backtrace<'a>(&mut self, backtrace: impl Iterator<Item = &'a u64>) {
-        self.sample_tree.value.increment_count();
-        add_backtrace(&mut self.sample_tree, backtrace);
+        self.add_backtrace_with_count(backtrace, 1);
+    }
+
+    /// Same as `add_backtrace`, but counts the backtrace as the specified number of samples.
+    pub fn add_backtrace_with_count<'a>(
+        &mut self,
+        backtrace: impl Iterator<Item = &'a u64>,
+        count: u32,
+    ) {
+        let Self {
+            nodes, child_nodes, ..
+        } = self;
+        nodes[ROOT].value.add_count(count);
+
+        let mut node = ROOT;
+        for &address in backtrace {
+            node = match find_child(nodes, child_nodes, node, address) {
+                Some(child) => {
+                    nodes[child].value.add_count(count);
+                    child
+                }
+                None => add_child(nodes, child_nodes, node, address, count),
+            };
+        }
     }
 
     pub fn sample_tree_dfs_iter(&self) -> impl Iterator<Item = &SamplePoint> {
-        // Provide the DFS iterator and skip the root node since it's not part of the sample trace.
-        self.sample_tree.dfs_preorder_iter().skip(1)
+        // Walk the tree in pre-order and skip the root node since it's not part of the sample trace.
+        // The next sibling of a node is visited after all of its descendants.
+        let mut pending: Vec<usize> = Vec::from_iter(self.nodes[ROOT].first_child);
+        std::iter::from_fn(move || {
+            let node = &self.nodes[pending.pop()?];
+            pending.extend(node.next_sibling);
+            pending.extend(node.first_child);
+            Some(&node.value)
+        })
+    }
+
+    /// Call the visitor once for every node in the sample tree where at least one backtrace ended,
+    /// with the path to that node, outermost frame first, and the number of backtraces that ended there.
+    pub fn visit_backtraces(&self, mut visitor: impl FnMut(&[&SamplePoint], u32)) {
+        self.visit_node_backtraces(ROOT, &mut Vec::new(), &mut visitor);
+    }
+
+    /// Generate the inverted sample tree, where the roots are the innermost frames of each
+    /// backtrace and the children are their callers.
+    pub fn inverted(&self) -> ThreadSample {
+        let mut inverted =
+            ThreadSample::new(self.thread_id, self.user_cpu_time, self.kernel_cpu_time);
+        self.visit_backtraces(|backtrace, count| {
+            let addresses: Vec<u64> = backtrace.iter().rev().map(|p| p.get_address()).collect();
+            inverted.add_backtrace_with_count(addresses.iter(), count);
+        });
+        inverted
+    }
+
+    /// Generate a copy of the sample tree without the nodes that have fewer than the specified
+    /// number of samples. The samples of removed nodes are attributed to their closest remaining
+    /// ancestor, so the counts of the remaining nodes are unchanged.
+    pub fn pruned(&self, min_count: u32) -> ThreadSample {
+        let mut pruned =
+            ThreadSample::new(self.thread_id, self.user_cpu_time, self.kernel_cpu_time);
+        self.visit_backtraces(|backtrace, count| {
+            let addresses: Vec<u64> = backtrace
+                .iter()
+                .take_while(|p| p.get_count() >= min_count)
+                .map(|p| p.get_address())
+                .collect();
+            pruned.add_backtrace_with_count(addresses.iter(), count);
+        });
+        pruned
+    }
+
+    fn visit_node_backtraces<'a>(
+        &'a self,
+        node: usize,
+        backtrace: &mut Vec<&'a SamplePoint>,
+        visitor: &mut impl FnMut(&[&'a SamplePoint], u32),
+    ) {
+        let children_count: u32 = children(&self.nodes, node)
+            .map(|child| self.nodes[child].value.get_count())
+            .sum();
+        let count = self.nodes[node].value.get_count();
+        if count > children_count {
+            visitor(backtrace, count - children_count);
+        }
+
+        for child in children(&self.nodes, node) {
+            backtrace.push(&self.nodes[child].value);
+            self.visit_node_backtraces(child, backtrace, visitor);
+            backtrace.pop();
+        }
     }
 }
 
-fn add_backtrace<'a>(
-    node: &mut TreeNode<SamplePoint>,
-    mut backtrace: impl Iterator<Item = &'a u64>,
-) {
-    let Some(&address) = backtrace.next() else {
-        return;
-    };
-
-    if let Some(node) = node
-        .children
-        .iter_mut()
-        .find(|n| n.value.get_address() == address)
-    {
-        node.value.increment_count();
-        add_backtrace(node, backtrace);
+fn find_child(
+    nodes: &[SampleNode],
+    child_nodes: &HashMap<(usize, u64), usize, BuildHasherDefault<ChildHasher>>,
+    node: usize,
+    address: u64,
+) -> Option<usize> {
+    if nodes[node].child_count > MAX_LINEAR_CHILDREN {
+        child_nodes.get(&(node, address)).copied()
     } else {
-        let mut child_node = TreeNode {
-            value: SamplePoint::new(node.value.get_level() + 1, address),
-            children: Vec::new(),
-        };
-        add_backtrace(&mut child_node, backtrace);
-        node.children.push(child_node);
+        children(nodes, node).find(|&child| nodes[child].value.get_address() == address)
+    }
+}
+
+fn add_child(
+    nodes: &mut Vec<SampleNode>,
+    child_nodes: &mut HashMap<(usize, u64), usize, BuildHasherDefault<ChildHasher>>,
+    node: usize,
+    address: u64,
+    count: u32,
+) -> usize {
+    let child = nodes.len();
+    let level = nodes[node].value.get_level() + 1;
+    nodes.push(SampleNode::new(SamplePoint::with_count(
+        level, address, count,
+    )));
+    match nodes[node].last_child {
+        Some(last_child) => nodes[last_child].next_sibling = Some(child),
+        None => nodes[node].first_child = Some(child),
+    }
+    nodes[node].last_child = Some(child);
+    nodes[node].child_count += 1;
+
+    if nodes[node].child_count == MAX_LINEAR_CHILDREN + 1 {
+        // The node just became too wide to search linearly, so index all of its children.
+        for sibling in children(nodes, node) {
+            child_nodes.insert((node, nodes[sibling].value.get_address()), sibling);
+        }
+    } else if nodes[node].child_count > MAX_LINEAR_CHILDREN {
+        child_nodes.insert((node, address), child);
+    }
+    child
+}
+
+/// Multiplicative hasher for the keys of the child nodes, which are only integers,
+/// so they don't need the collision resistance of the default hasher.
+#[derive(Default)]
+struct ChildHasher(u64);
+
+impl Hasher for ChildHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.0 = (self.0.rotate_left(5) ^ value).wrapping_mul(0x517c_c1b7_2722_0a95);
     }
+
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+}
+
+/// Iterate over the indices of the children of the specified node.
+fn children(nodes: &[SampleNode], node: usize) -> impl Iterator<Item = usize> + '_ {
+    std::iter::successors(nodes[node].first_child, |&child| nodes[child].next_sibling)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// The level, address and count of each sample point, in depth-first pre-order.
+    fn sample_tree(thread_sample: &ThreadSample) -> Vec<(u32, u64, u32)> {
+        thread_sample
+            .sample_tree_dfs_iter()
+            .map(|p| (p.get_level(), p.get_address(), p.get_count()))
+            .collect()
+    }
+
     #[test]
     fn test_empty_thread_sample() {
         let thread_sample = ThreadSample::new(1, Duration::default(), Duration::default());
 
         assert_eq!(thread_sample.thread_id, 1);
-        assert_eq!(
-            format!("{:?}", thread_sample),
-            "ThreadSample { thread_id: 1, user_cpu_time: 0ns, kernel_cpu_time: 0ns, sample_tree: \
-                TreeNode { value: SamplePoint { level: 0, address: 0, count: 0 }, children: [] } }"
-        );
+        assert_eq!(thread_sample.get_sample_count(), 0);
+        assert_eq!(sample_tree(&thread_sample), vec![]);
     }
 
     #[test]
@@ -96,13 +276,10 @@ mod tests {
 
         thread_sample.add_backtrace([1, 2, 3].iter());
 
+        assert_eq!(thread_sample.get_sample_count(), 1);
         assert_eq!(
-            format!("{:?}", thread_sample),
-            "ThreadSample { thread_id: 1, user_cpu_time: 0ns, kernel_cpu_time: 0ns, sample_tree: \
-                TreeNode { value: SamplePoint { level: 0, address: 0, count: 1 }, children: [\
-                    TreeNode { value: SamplePoint { level: 1, address: 1, count: 1 }, children: [\
-                        TreeNode { value: SamplePoint { level: 2, address: 2, count: 1 }, children: [\
-                            TreeNode { value: SamplePoint { level: 3, address: 3, count: 1 }, children: [] }] }] }] } }"
+            sample_tree(&thread_sample),
+            vec![(1, 1, 1), (2, 2, 1), (3, 3, 1)]
         );
     }
 
@@ -113,10 +290,19 @@ mod tests {
         thread_sample.add_backtrace([1, 2].iter());
         thread_sample.add_backtrace([1, 2].iter());
 
-        assert_eq!(format!("{:?}", thread_sample), "ThreadSample { thread_id: 1, user_cpu_time: 0ns, kernel_cpu_time: 0ns, sample_tree: \
-            TreeNode { value: SamplePoint { level: 0, address: 0, count: 2 }, children: [\
-                TreeNode { value: SamplePoint { level: 1, address: 1, count: 2 }, children: [\
-                    TreeNode { value: SamplePoint { level: 2, address: 2, count: 2 }, children: [] }] }] } }");
+        assert_eq!(thread_sample.get_sample_count(), 2);
+        assert_eq!(sample_tree(&thread_sample), vec![(1, 1, 2), (2, 2, 2)]);
+    }
+
+    #[test]
+    fn test_thread_sample_backtrace_with_count() {
+        let mut thread_sample = ThreadSample::new(1, Duration::default(), Duration::default());
+
+        thread_sample.add_backtrace_with_count([1, 2].iter(), 3);
+        thread_sample.add_backtrace([1].iter());
+
+        assert_eq!(thread_sample.get_sample_count(), 4);
+        assert_eq!(sample_tree(&thread_sample), vec![(1, 1, 4), (2, 2, 3)]);
     }
 
     #[test]
@@ -126,10 +312,62 @@ mod tests {
         thread_sample.add_backtrace([1, 2].iter());
         thread_sample.add_backtrace([1, 3].iter());
 
-        assert_eq!(format!("{:?}", thread_sample), "ThreadSample { thread_id: 1, user_cpu_time: 0ns, kernel_cpu_time: 0ns, sample_tree: \
-            TreeNode { value: SamplePoint { level: 0, address: 0, count: 2 }, children: [\
-                TreeNode { value: SamplePoint { level: 1, address: 1, count: 2 }, children: [\
-                    TreeNode { value: SamplePoint { level: 2, address: 2, count: 1 }, children: [] }, \
-                    TreeNode { value: SamplePoint { level: 2, address: 3, count: 1 }, children: [] }] }] } }");
+        assert_eq!(thread_sample.get_sample_count(), 2);
+        assert_eq!(
+            sample_tree(&thread_sample),
+            vec![(1, 1, 2), (2, 2, 1), (2, 3, 1)]
+        );
+    }
+
+    #[test]
+    fn test_thread_sample_children_keep_insertion_order() {
+        let mut thread_sample = ThreadSample::new(1, Duration::default(), Duration::default());
+
+        thread_sample.add_backtrace([1, 5, 6].iter());
+        thread_sample.add_backtrace([1, 3].iter());
+        thread_sample.add_backtrace([1, 5, 7].iter());
+        thread_sample.add_backtrace([2].iter());
+
+        assert_eq!(
+            sample_tree(&thread_sample),
+            vec![
+                (1, 1, 3),
+                (2, 5, 2),
+                (3, 6, 1),
+                (3, 7, 1),
+                (2, 3, 1),
+                (1, 2, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thread_sample_inverted() {
+        let mut thread_sample = ThreadSample::new(1, Duration::default(), Duration::default());
+
+        thread_sample.add_backtrace([1, 2, 3].iter());
+        thread_sample.add_backtrace([1, 3].iter());
+        thread_sample.add_backtrace([1].iter());
+
+        let inverted = thread_sample.inverted();
+        assert_eq!(inverted.get_sample_count(), 3);
+        assert_eq!(
+            sample_tree(&inverted),
+            vec![(1, 1, 1), (1, 3, 2), (2, 2, 1), (3, 1, 1), (2, 1, 1)]
+        );
+    }
+
+    #[test]
+    fn test_thread_sample_pruned() {
+        let mut thread_sample = ThreadSample::new(1, Duration::default(), Duration::default());
+
+        thread_sample.add_backtrace([1, 2, 3].iter());
+        thread_sample.add_backtrace([1, 2, 3].iter());
+        thread_sample.add_backtrace([1, 2, 4].iter());
+        thread_sample.add_backtrace([1, 5].iter());
+
+        let pruned = thread_sample.pruned(2);
+        assert_eq!(pruned.get_sample_count(), 4);
+        assert_eq!(sample_tree(&pruned), vec![(1, 1, 4), (2, 2, 3), (3, 3, 2)]);
     }
 }
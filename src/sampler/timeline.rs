@@ -0,0 +1,187 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use super::*;
+
+/// The samples of a single thread in the order they were taken.
+#[derive(Debug)]
+pub struct ThreadTimeline {
+    thread_id: Tid,
+    samples: Vec<TimelineSample>,
+}
+
+#[derive(Debug)]
+pub struct TimelineSample {
+    tick: u64,
+    timestamp: Duration,
+    user_cpu_time: Duration,
+    kernel_cpu_time: Duration,
+    stack_id: Option<StackId>,
+}
+
+impl ThreadTimeline {
+    pub fn new(thread_id: Tid) -> Self {
+        Self {
+            thread_id,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Append a sample, which is expected to be taken after all previously added samples.
+    pub fn add_sample(&mut self, sample: TimelineSample) {
+        self.samples.push(sample);
+    }
+
+    pub fn get_thread_id(&self) -> Tid {
+        self.thread_id
+    }
+
+    pub fn get_samples(&self) -> &[TimelineSample] {
+        &self.samples
+    }
+
+    /// Build the sample tree of the samples taken within the specified time range.
+    /// The CPU times are the CPU times the thread consumed since the last sample before the range,
+    /// or since the first sample in range if there is none.
+    pub fn thread_sample(&self, range: &Range<Duration>, stack_table: &StackTable) -> ThreadSample {
+        let indices = self.window_indices(range);
+        let window = &self.samples[indices.clone()];
+        let previous = indices
+            .start
+            .checked_sub(1)
+            .map(|index| &self.samples[index]);
+        let (user_cpu_time, kernel_cpu_time) = match (previous.or(window.first()), window.last()) {
+            (Some(first), Some(last)) => (
+                last.user_cpu_time.saturating_sub(first.user_cpu_time),
+                last.kernel_cpu_time.saturating_sub(first.kernel_cpu_time),
+            ),
+            _ => Default::default(),
+        };
+
+        let mut thread_sample = ThreadSample::new(self.thread_id, user_cpu_time, kernel_cpu_time);
+        for sample in window {
+            thread_sample.add_backtrace(stack_table.backtrace(sample.stack_id).iter());
+        }
+        thread_sample
+    }
+
+    fn window_indices(&self, range: &Range<Duration>) -> Range<usize> {
+        let start = self
+            .samples
+            .partition_point(|sample| sample.timestamp < range.start);
+        let end = self
+            .samples
+            .partition_point(|sample| sample.timestamp < range.end);
+        start..end.max(start)
+    }
+
+    /// Remove all samples taken outside of the specified time range.
+    pub fn retain_window(&mut self, range: &Range<Duration>) {
+        let indices = self.window_indices(range);
+        self.samples.truncate(indices.end);
+        self.samples.drain(..indices.start);
+    }
+}
+
+impl TimelineSample {
+    /// The CPU times are the thread's cumulative CPU times at the time of the sample,
+    /// and the stack is `None` when the backtrace was empty.
+    pub fn new(
+        tick: u64,
+        timestamp: Duration,
+        user_cpu_time: Duration,
+        kernel_cpu_time: Duration,
+        stack_id: Option<StackId>,
+    ) -> Self {
+        Self {
+            tick,
+            timestamp,
+            user_cpu_time,
+            kernel_cpu_time,
+            stack_id,
+        }
+    }
+
+    /// The sequence number of the snapshot this sample was taken in.
+    pub fn get_tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// The time the sample was taken, relative to the start of the capture.
+    pub fn get_timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    pub fn get_user_cpu_time(&self) -> Duration {
+        self.user_cpu_time
+    }
+
+    pub fn get_kernel_cpu_time(&self) -> Duration {
+        self.kernel_cpu_time
+    }
+
+    /// The stack of the sample within the stack table of the capture.
+    pub fn get_stack_id(&self) -> Option<StackId> {
+        self.stack_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeline(stack_table: &mut StackTable) -> ThreadTimeline {
+        let mut timeline = ThreadTimeline::new(1);
+        for (index, backtrace) in [[1, 2], [1, 3], [1, 3], [4, 5]].into_iter().enumerate() {
+            timeline.add_sample(TimelineSample::new(
+                index as u64,
+                Duration::from_millis(10 * index as u64),
+                Duration::from_millis(2 * index as u64),
+                Duration::from_millis(index as u64),
+                stack_table.intern(backtrace),
+            ));
+        }
+        timeline
+    }
+
+    #[test]
+    fn test_retain_window() {
+        let mut timeline = timeline(&mut StackTable::new());
+
+        timeline.retain_window(&(Duration::from_millis(5)..Duration::from_millis(30)));
+        let ticks: Vec<u64> = timeline
+            .get_samples()
+            .iter()
+            .map(TimelineSample::get_tick)
+            .collect();
+        assert_eq!(ticks, vec![1, 2]);
+
+        timeline.retain_window(&(Duration::from_millis(31)..Duration::from_millis(5)));
+        assert!(timeline.get_samples().is_empty());
+    }
+
+    #[test]
+    fn test_thread_sample_of_window() {
+        let mut stack_table = StackTable::new();
+        let timeline = timeline(&mut stack_table);
+
+        let thread_sample = timeline.thread_sample(
+            &(Duration::from_millis(10)..Duration::from_millis(30)),
+            &stack_table,
+        );
+
+        assert_eq!(thread_sample.get_sample_count(), 2);
+        assert_eq!(thread_sample.get_user_cpu_time(), Duration::from_millis(4));
+        assert_eq!(
+            thread_sample.get_kernel_cpu_time(),
+            Duration::from_millis(2)
+        );
+        assert_eq!(
+            thread_sample
+                .sample_tree_dfs_iter()
+                .map(|sample_point| (sample_point.get_address(), sample_point.get_count()))
+                .collect::<Vec<_>>(),
+            vec![(1, 2), (3, 2)]
+        );
+    }
+}